@@ -1,48 +1,216 @@
-use crate::module_config::{ModuleConfigInfo, RackInput, RackOutput, StaticModuleConfig};
-use crate::rack::{InputPort, OutputPort, Port, PORT_MAX_CHANNELS};
-use crate::util::gate;
+use crate::module_config::{
+    ModuleConfigInfo, RackInput, RackOutput, RackParams, StaticModuleConfig,
+};
+use crate::rack::{
+    Expanders, InputPort, ModuleParam, OutputPort, Param, Port, DEFAULT_SAMPLE_RATE,
+    PORT_MAX_CHANNELS,
+};
+use crate::util::{gate, OutputPulse};
 
-const THRESHOLD: f32 = 12.0;
+use crate::ffi::bridge::{BreakerLinkMessage, TripCause, TripEvent};
+
+mod detector;
+use detector::{DetectorConfig, LevelDetector, TripMode};
+
+mod limiter;
+use limiter::{Limiter, LimiterConfig, Protection};
+
+mod trip_log;
+use trip_log::TripLog;
+pub use trip_log::TripLogSnapshot;
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const ZEROES: [f32; PORT_MAX_CHANNELS] = [0.0; PORT_MAX_CHANNELS];
 
+// The stereo Breaker module.
+pub type Breaker = BreakerBus<2>;
+
+// A wider Breaker with eight signal inputs and outputs sharing one breaker
+// state, for protecting a whole submix at once.
+pub type Breaker8 = BreakerBus<8>;
+
 impl StaticModuleConfig for Breaker {
     const INPUT_PORTS: &'static [&'static std::ffi::CStr] = &[c"Left", c"Right", c"Reset trigger"];
 
-    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[c"Tripped gate", c"Left", c"Right"];
+    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Tripped gate",
+        c"Left",
+        c"Right",
+        c"Trip trigger",
+        c"Reset trigger",
+    ];
 }
 
-struct BreakerInputs<'a> {
-    left: InputPort<'a>,
-    right: InputPort<'a>,
+impl StaticModuleConfig for Breaker8 {
+    const INPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Input 1",
+        c"Input 2",
+        c"Input 3",
+        c"Input 4",
+        c"Input 5",
+        c"Input 6",
+        c"Input 7",
+        c"Input 8",
+        c"Reset trigger",
+    ];
+
+    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Tripped gate",
+        c"Output 1",
+        c"Output 2",
+        c"Output 3",
+        c"Output 4",
+        c"Output 5",
+        c"Output 6",
+        c"Output 7",
+        c"Output 8",
+        c"Trip trigger",
+        c"Reset trigger",
+    ];
+}
+
+// The N signal inputs come first, followed by the reset trigger.
+struct BreakerInputs<'a, const N: usize> {
+    signals: [InputPort<'a>; N],
     reset_trigger: InputPort<'a>,
 }
-impl RackInput for BreakerInputs<'_> {
-    const COUNT: usize = 3;
+impl<const N: usize> RackInput for BreakerInputs<'_, N> {
+    const COUNT: usize = N + 1;
 
     fn from_raw_ptr(ports: *const Port) -> Self {
         let in_port = |ptr: *const Port, index: usize| {
             debug_assert!(index < Self::COUNT);
             InputPort::from_raw_port_index(ptr, index)
         };
-        let left = in_port(ports, 0);
-        let right = in_port(ports, 1);
-        let reset_trigger = in_port(ports, 2);
+        let signals = core::array::from_fn(|index| in_port(ports, index));
+        let reset_trigger = in_port(ports, N);
         BreakerInputs {
-            left,
-            right,
+            signals,
             reset_trigger,
         }
     }
 }
 
-struct BreakerOutputs<'a> {
+struct BreakerParams<'a> {
+    trip_mode: ModuleParam<'a>,
+    trip_window: ModuleParam<'a>,
+    dc_detect: ModuleParam<'a>,
+    dc_limit: ModuleParam<'a>,
+    growth_detect: ModuleParam<'a>,
+    growth_time: ModuleParam<'a>,
+    reset_button: ModuleParam<'a>,
+    trip_button: ModuleParam<'a>,
+    armed: ModuleParam<'a>,
+    protection: ModuleParam<'a>,
+    limit_attack: ModuleParam<'a>,
+    limit_release: ModuleParam<'a>,
+    normal: ModuleParam<'a>,
+    slew_detect: ModuleParam<'a>,
+    slew_limit: ModuleParam<'a>,
+    link: ModuleParam<'a>,
+}
+
+// The index of each param, which must match BreakerParamId on the C++ side.
+impl BreakerParams<'_> {
+    const TRIP_MODE: usize = 0;
+    const TRIP_WINDOW: usize = 1;
+    const DC_DETECT: usize = 2;
+    const DC_LIMIT: usize = 3;
+    const GROWTH_DETECT: usize = 4;
+    const GROWTH_TIME: usize = 5;
+    const RESET_BUTTON: usize = 6;
+    const TRIP_BUTTON: usize = 7;
+    const ARMED: usize = 8;
+    const PROTECTION: usize = 9;
+    const LIMIT_ATTACK: usize = 10;
+    const LIMIT_RELEASE: usize = 11;
+    const NORMAL: usize = 12;
+    const SLEW_DETECT: usize = 13;
+    const SLEW_LIMIT: usize = 14;
+    const LINK: usize = 15;
+}
+
+impl RackParams for BreakerParams<'_> {
+    const COUNT: usize = 16;
+
+    fn from_raw_ptr(params: *const Param) -> Self {
+        let param = |ptr: *const Param, index: usize| {
+            debug_assert!(index < Self::COUNT);
+            ModuleParam::from_raw_param_index(ptr, index)
+        };
+        let trip_mode = param(params, Self::TRIP_MODE);
+        let trip_window = param(params, Self::TRIP_WINDOW);
+        let dc_detect = param(params, Self::DC_DETECT);
+        let dc_limit = param(params, Self::DC_LIMIT);
+        let growth_detect = param(params, Self::GROWTH_DETECT);
+        let growth_time = param(params, Self::GROWTH_TIME);
+        let reset_button = param(params, Self::RESET_BUTTON);
+        let trip_button = param(params, Self::TRIP_BUTTON);
+        let armed = param(params, Self::ARMED);
+        let protection = param(params, Self::PROTECTION);
+        let limit_attack = param(params, Self::LIMIT_ATTACK);
+        let limit_release = param(params, Self::LIMIT_RELEASE);
+        let normal = param(params, Self::NORMAL);
+        let slew_detect = param(params, Self::SLEW_DETECT);
+        let slew_limit = param(params, Self::SLEW_LIMIT);
+        let link = param(params, Self::LINK);
+        BreakerParams {
+            trip_mode,
+            trip_window,
+            dc_detect,
+            dc_limit,
+            growth_detect,
+            growth_time,
+            reset_button,
+            trip_button,
+            armed,
+            protection,
+            limit_attack,
+            limit_release,
+            normal,
+            slew_detect,
+            slew_limit,
+            link,
+        }
+    }
+}
+
+impl BreakerParams<'_> {
+    fn detector_config(&self, sample_rate: f32) -> DetectorConfig {
+        let enabled = |switch: &ModuleParam, value: &ModuleParam| {
+            (switch.get_index() != 0).then(|| value.get_value())
+        };
+        DetectorConfig {
+            mode: TripMode::from_index(self.trip_mode.get_index()),
+            window_ms: self.trip_window.get_value(),
+            dc_limit: enabled(&self.dc_detect, &self.dc_limit),
+            growth_ms: enabled(&self.growth_detect, &self.growth_time),
+            slew_limit: enabled(&self.slew_detect, &self.slew_limit),
+            sample_rate,
+        }
+    }
+
+    fn limiter_config(&self, sample_rate: f32) -> LimiterConfig {
+        LimiterConfig {
+            attack_ms: self.limit_attack.get_value(),
+            release_ms: self.limit_release.get_value(),
+            sample_rate,
+        }
+    }
+}
+
+// The tripped gate comes first, followed by the N signal outputs, and then
+// the trip and reset triggers.
+struct BreakerOutputs<'a, const N: usize> {
     tripped_gate: OutputPort<'a>,
-    left: OutputPort<'a>,
-    right: OutputPort<'a>,
+    signals: [OutputPort<'a>; N],
+    trip_trigger: OutputPort<'a>,
+    reset_trigger: OutputPort<'a>,
 }
-impl RackOutput for BreakerOutputs<'_> {
-    const COUNT: usize = 3;
+impl<const N: usize> RackOutput for BreakerOutputs<'_, N> {
+    const COUNT: usize = N + 3;
 
     fn from_raw_ptr(ports: *mut Port) -> Self {
         let out_port = |ptr: *mut Port, index: usize| {
@@ -50,12 +218,14 @@ impl RackOutput for BreakerOutputs<'_> {
             OutputPort::from_raw_port_index(ptr, index)
         };
         let tripped_gate = out_port(ports, 0);
-        let left = out_port(ports, 1);
-        let right = out_port(ports, 2);
+        let signals = core::array::from_fn(|index| out_port(ports, index + 1));
+        let trip_trigger = out_port(ports, N + 1);
+        let reset_trigger = out_port(ports, N + 2);
         BreakerOutputs {
             tripped_gate,
-            left,
-            right,
+            signals,
+            trip_trigger,
+            reset_trigger,
         }
     }
 }
@@ -67,44 +237,149 @@ enum BreakerState {
     Open,
 }
 
-#[derive(Default)]
-pub struct Breaker {
+// A circuit breaker over N signal inputs, which all trip and reset together.
+pub struct BreakerBus<const N: usize> {
     state: BreakerState,
     reset_trigger: crate::util::InputTrigger,
+    reset_button: crate::util::InputTrigger,
+    trip_button: crate::util::InputTrigger,
+    trip_pulse: OutputPulse,
+    reset_pulse: OutputPulse,
+    sample_rate: f32,
+    detector: LevelDetector<N>,
+    limiter: Limiter,
+    trip_log: TripLog,
+    trip_log_snapshot: Arc<TripLogSnapshot>,
+    // Whether the log has changed since it was last published to the
+    // snapshot.
+    trip_log_changed: bool,
 }
 
-impl Breaker {
+impl<const N: usize> BreakerBus<N> {
+    pub fn new() -> Self {
+        BreakerBus {
+            state: BreakerState::default(),
+            reset_trigger: crate::util::InputTrigger::new(),
+            reset_button: crate::util::InputTrigger::new(),
+            trip_button: crate::util::InputTrigger::new(),
+            trip_pulse: OutputPulse::new(),
+            reset_pulse: OutputPulse::new(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            detector: LevelDetector::new(DEFAULT_SAMPLE_RATE),
+            limiter: Limiter::new(DEFAULT_SAMPLE_RATE),
+            trip_log: TripLog::default(),
+            trip_log_snapshot: Arc::default(),
+            trip_log_changed: false,
+        }
+    }
+
     fn process(
         &mut self,
-        inputs: &BreakerInputs,
-        outputs: &mut BreakerOutputs,
+        inputs: &BreakerInputs<N>,
+        outputs: &mut BreakerOutputs<N>,
+        params: &BreakerParams,
+        expanders: &mut Expanders<BreakerLinkMessage>,
         tripped_status: &mut bool,
     ) {
         use BreakerState::*;
 
-        // If we received a reset trigger, close the breaker.
+        if self.trip_log_snapshot.take_clear_request() {
+            self.trip_log.clear();
+            self.trip_log_changed = true;
+        }
+
+        self.detector
+            .configure(params.detector_config(self.sample_rate));
+        self.limiter
+            .configure(params.limiter_config(self.sample_rate));
+
+        // Trip and reset events from linked Breakers on either side. If we
+        // aren't linked, we ignore our neighbours.
+        let linked = params.link.get_index() != 0;
+        let receive = |message: Option<&BreakerLinkMessage>| match message {
+            Some(message) if linked => *message,
+            _ => BreakerLinkMessage::default(),
+        };
+        let from_left = receive(expanders.from_left);
+        let from_right = receive(expanders.from_right);
+
+        // If we received a reset trigger, or the reset button was pressed,
+        // close the breaker.
         let reset_trigger_voltage = inputs.reset_trigger.get_zero_normaled_monophonic_voltage();
-        if self.reset_trigger.process_voltage(reset_trigger_voltage) {
-            self.state = Closed;
+        let reset_triggered = self.reset_trigger.process_voltage(reset_trigger_voltage);
+        let reset_pressed = self
+            .reset_button
+            .process_voltage(params.reset_button.get_value());
+        let local_reset = reset_triggered || reset_pressed;
+        if local_reset || from_left.reset || from_right.reset {
+            self.reset();
+        }
+
+        // The trip button opens the breaker no matter what the inputs are
+        // doing, and so does a trip anywhere else in a linked group.
+        let trip_pressed = self
+            .trip_button
+            .process_voltage(params.trip_button.get_value());
+        let mut local_trip = false;
+        if trip_pressed && matches!(self.state, Closed) {
+            local_trip = true;
+            self.trip(manual_trip_event(TripCause::Manual));
+        }
+        if (from_left.trip || from_right.trip) && matches!(self.state, Closed) {
+            self.trip(manual_trip_event(TripCause::Linked));
+        }
+
+        // With normalling on, an unpatched input takes its signal from the one
+        // before it, so that a mono source can feed a stereo pair.
+        let mut sources: [&InputPort; N] = core::array::from_fn(|i| &inputs.signals[i]);
+        if params.normal.get_index() != 0 {
+            for i in 1..N {
+                if !sources[i].is_connected() {
+                    sources[i] = sources[i - 1];
+                }
+            }
         }
 
         // If any of our input channels has a value out of range, trip the
-        // breaker.
+        // breaker. Every port goes through the detector so that its level
+        // history stays up to date, even once we know we're tripping.
         if matches!(self.state, Closed) {
-            let left_in = inputs.left.as_slice();
-            let right_in = inputs.right.as_slice();
-            let tripped = left_in
-                .map(|left| out_of_range(left, THRESHOLD))
-                .unwrap_or(false)
-                || right_in
-                    .map(|right| out_of_range(right, THRESHOLD))
-                    .unwrap_or(false);
-            if tripped {
-                self.state = Open;
+            let mut trip = None;
+            for (port, input) in sources.iter().enumerate() {
+                let detection = self.detector.process_port(port, input.as_slice());
+                trip = trip.or(detection.map(|d| (port, d)));
+            }
+            if let Some((port, detection)) = trip {
+                local_trip = true;
+                self.trip(TripEvent {
+                    time: trip_time(),
+                    port,
+                    channel: detection.channel,
+                    level: detection.level,
+                    cause: detection.cause,
+                });
             }
         }
 
-        let mute = match self.state {
+        // Pass our own events along the row in both directions, along with
+        // whatever came from the other side. Events never travel back the way
+        // they came, so they can't bounce around the group forever.
+        let send = |from_other_side: BreakerLinkMessage| BreakerLinkMessage {
+            trip: linked && (local_trip || from_other_side.trip),
+            reset: linked && (local_reset || from_other_side.reset),
+        };
+        expanders.send(send(from_right), send(from_left));
+
+        let sample_time = self.sample_rate.recip();
+        let trip_voltage = self.trip_pulse.process(sample_time);
+        outputs.trip_trigger.set_monophonic_voltage(trip_voltage);
+        let reset_voltage = self.reset_pulse.process(sample_time);
+        outputs.reset_trigger.set_monophonic_voltage(reset_voltage);
+
+        // While disarmed, the breaker still trips and reports it, but the
+        // signals pass through regardless.
+        let armed = params.armed.get_index() != 0;
+        let protect = match self.state {
             Closed => {
                 outputs.tripped_gate.set_monophonic_voltage(gate::LOW);
                 *tripped_status = false;
@@ -113,33 +388,129 @@ impl Breaker {
             Open => {
                 outputs.tripped_gate.set_monophonic_voltage(gate::HIGH);
                 *tripped_status = true;
-                true
+                armed
             }
         };
-        copy_or_mute(&inputs.left, &mut outputs.left, mute);
-        copy_or_mute(&inputs.right, &mut outputs.right, mute);
+        let protection = protect.then(|| Protection::from_index(params.protection.get_index()));
+        let signals = sources.iter().zip(outputs.signals.iter_mut());
+        match protection {
+            None => {
+                signals.for_each(|(input, output)| copy_or_mute(input, output, false));
+            }
+            Some(Protection::Mute) => {
+                signals.for_each(|(input, output)| copy_or_mute(input, output, true));
+            }
+            Some(Protection::Limit) => {
+                let peak = sources
+                    .iter()
+                    .flat_map(|input| input.as_slice().unwrap_or_default())
+                    .fold(0.0, |peak: f32, value| peak.max(value.abs()));
+                let gain = self.limiter.process(peak);
+                signals.for_each(|(input, output)| copy_map(input, output, |v| v * gain));
+            }
+            Some(Protection::SoftClip) => {
+                signals.for_each(|(input, output)| copy_map(input, output, limiter::soft_clip));
+            }
+        }
+
+        self.publish_trip_log();
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn process_raw(
         &mut self,
         inputs: *const Port,
         outputs: *mut Port,
+        params: *const Param,
+        from_left: *const BreakerLinkMessage,
+        from_right: *const BreakerLinkMessage,
+        to_left: *mut BreakerLinkMessage,
+        to_right: *mut BreakerLinkMessage,
         tripped_status: &mut bool,
     ) {
         let inputs = BreakerInputs::from_raw_ptr(inputs);
-        let mut outputs: BreakerOutputs = BreakerOutputs::from_raw_ptr(outputs);
-        self.process(&inputs, &mut outputs, tripped_status)
+        let mut outputs = BreakerOutputs::from_raw_ptr(outputs);
+        let params = BreakerParams::from_raw_ptr(params);
+        let mut expanders = Expanders::from_raw_ptrs(from_left, from_right, to_left, to_right);
+        self.process(
+            &inputs,
+            &mut outputs,
+            &params,
+            &mut expanders,
+            tripped_status,
+        )
+    }
+
+    // Open the breaker, and log why.
+    fn trip(&mut self, event: TripEvent) {
+        self.state = BreakerState::Open;
+        self.trip_pulse.trigger();
+        self.trip_log.record(event);
+        self.trip_log_changed = true;
     }
 
-    pub fn get_module_config_info(&self) -> *mut ModuleConfigInfo {
+    // Close the breaker, and start its level history from scratch.
+    fn reset(&mut self) {
+        if matches!(self.state, BreakerState::Open) {
+            self.reset_pulse.trigger();
+        }
+        self.state = BreakerState::Closed;
+        self.detector.reset();
+        self.limiter.reset();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    // Copy the trip log to the snapshot if it has changed, or leave it for a
+    // later frame if the snapshot is busy.
+    fn publish_trip_log(&mut self) {
+        if self.trip_log_changed {
+            self.trip_log_changed = !self.trip_log_snapshot.publish(&self.trip_log);
+        }
+    }
+
+    // The trip log as seen from other threads. The pointer lives as long as
+    // the breaker does.
+    pub fn get_trip_log(&self) -> *const TripLogSnapshot {
+        Arc::as_ptr(&self.trip_log_snapshot)
+    }
+
+    // These restore a saved trip log, and mustn't be called while the engine
+    // might be processing the breaker.
+    pub fn set_trip_count(&mut self, count: u64) {
+        self.trip_log.set_count(count);
+        self.trip_log_changed = true;
+        self.publish_trip_log();
+    }
+
+    // Put a saved event back into the log. Events should be restored oldest
+    // first.
+    pub fn restore_trip_event(&mut self, event: TripEvent) {
+        self.trip_log.push(event);
+        self.trip_log_changed = true;
+        self.publish_trip_log();
+    }
+
+    pub fn clear_trip_log(&mut self) {
+        self.trip_log.clear();
+        self.trip_log_changed = true;
+        self.publish_trip_log();
+    }
+
+    pub fn get_module_config_info(&self) -> *mut ModuleConfigInfo
+    where
+        Self: StaticModuleConfig,
+    {
         ModuleConfigInfo::from_module_instance(self).into_ptr()
     }
 }
 
-fn out_of_range(values: &[f32], threshold: f32) -> bool {
-    // Note that depending on exactly how we do this comparison, NaNs may or may
-    // not trigger it. Currently we opt to let NaNs through without triggering.
-    values.iter().any(|n| n.abs() >= threshold)
+impl<const N: usize> Default for BreakerBus<N> {
+    fn default() -> Self {
+        BreakerBus::new()
+    }
 }
 
 fn copy_or_mute(src: &InputPort, dest: &mut OutputPort, mute: bool) {
@@ -157,3 +528,438 @@ fn copy_or_mute(src: &InputPort, dest: &mut OutputPort, mute: bool) {
         }
     }
 }
+
+// A log entry for a trip that didn't come from any of our inputs.
+fn manual_trip_event(cause: TripCause) -> TripEvent {
+    TripEvent {
+        time: trip_time(),
+        port: 0,
+        channel: 0,
+        level: 0.0,
+        cause,
+    }
+}
+
+// The time to log a trip at, in seconds since the Unix epoch. Unlike the
+// engine's frame count, wall-clock time still means something after the patch
+// is saved and reopened, and doesn't depend on the sample rate.
+fn trip_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+// Copy a port's voltages through a function.
+fn copy_map(src: &InputPort, dest: &mut OutputPort, f: impl Fn(f32) -> f32) {
+    match src.as_slice() {
+        Some(voltages) => {
+            let mut buffer = [0.0; PORT_MAX_CHANNELS];
+            let buffer = &mut buffer[..voltages.len()];
+            buffer
+                .iter_mut()
+                .zip(voltages)
+                .for_each(|(o, i)| *o = f(*i));
+            dest.set_voltages_from_slice(buffer);
+        }
+        None => {
+            dest.set_polyphony_count(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use detector::THRESHOLD;
+
+    // Param values in BreakerParams order.
+    type ParamValues = [f32; BreakerParams::COUNT];
+
+    fn trip_mode_params(mode: TripMode, window_ms: f32) -> ParamValues {
+        let mut values = [0.0; BreakerParams::COUNT];
+        values[BreakerParams::TRIP_MODE] = mode as usize as f32;
+        values[BreakerParams::TRIP_WINDOW] = window_ms;
+        values[BreakerParams::DC_LIMIT] = 1.0;
+        values[BreakerParams::GROWTH_TIME] = 500.0;
+        values[BreakerParams::ARMED] = 1.0;
+        values[BreakerParams::LIMIT_ATTACK] = Limiter::DEFAULT_ATTACK_MS;
+        values[BreakerParams::LIMIT_RELEASE] = Limiter::DEFAULT_RELEASE_MS;
+        values[BreakerParams::SLEW_LIMIT] = 200.0;
+        values
+    }
+
+    // Run a frame through process_raw() for a breaker with no linked
+    // neighbours, returning the tripped status.
+    fn process_unlinked<const N: usize>(
+        breaker: &mut BreakerBus<N>,
+        ins: &[Port],
+        outs: &mut [Port],
+        param_values: &ParamValues,
+    ) -> bool {
+        assert_eq!(ins.len(), N + 1);
+        assert_eq!(outs.len(), N + 3);
+        let params = param_values.map(Param::new);
+        let mut tripped_status = false;
+        breaker.process_raw(
+            ins.as_ptr(),
+            outs.as_mut_ptr(),
+            params.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut tripped_status,
+        );
+        tripped_status
+    }
+
+    // Run a single frame through the breaker with the given left input
+    // voltage and param values, returning the tripped status.
+    fn process_frame(breaker: &mut Breaker, left: f32, param_values: &ParamValues) -> bool {
+        process_frame_output(breaker, left, param_values).0
+    }
+
+    // As process_frame(), but also returning the left output voltage.
+    fn process_frame_output(
+        breaker: &mut Breaker,
+        left: f32,
+        param_values: &ParamValues,
+    ) -> (bool, f32) {
+        let mut left_in = Port::default();
+        OutputPort::wrap(&mut left_in).set_monophonic_voltage(left);
+        let right_in = Port::default();
+        let reset_in = Port::default();
+        let mut outs: [Port; 5] = Default::default();
+        let params = param_values.map(Param::new);
+
+        let inputs = BreakerInputs {
+            signals: [InputPort::wrap(&left_in), InputPort::wrap(&right_in)],
+            reset_trigger: InputPort::wrap(&reset_in),
+        };
+        let [tripped_gate, left_out, right_out, trip_trigger, reset_trigger] = &mut outs;
+        let mut outputs = BreakerOutputs {
+            tripped_gate: OutputPort::wrap(tripped_gate),
+            signals: [OutputPort::wrap(left_out), OutputPort::wrap(right_out)],
+            trip_trigger: OutputPort::wrap(trip_trigger),
+            reset_trigger: OutputPort::wrap(reset_trigger),
+        };
+        let params = BreakerParams::from_raw_ptr(params.as_ptr());
+        let mut tripped_status = false;
+        breaker.process(
+            &inputs,
+            &mut outputs,
+            &params,
+            &mut Expanders::none(),
+            &mut tripped_status,
+        );
+        let left_out = InputPort::wrap(left_out).get_monophonic_voltage();
+        (tripped_status, left_out.unwrap_or(0.0))
+    }
+
+    #[test]
+    fn test_trip_modes() {
+        // 1 ms at 44.1 kHz.
+        let window_ms = 1.0;
+        let window_samples = 44;
+        let peak = trip_mode_params(TripMode::Peak, window_ms);
+        let sustained = trip_mode_params(TripMode::SustainedPeak, window_ms);
+        let rms = trip_mode_params(TripMode::Rms, window_ms);
+
+        // A single spike trips on peak, but not on a sustained peak.
+        let mut b = Breaker::new();
+        assert!(process_frame(&mut b, THRESHOLD, &peak));
+        let mut b = Breaker::new();
+        assert!(!process_frame(&mut b, THRESHOLD, &sustained));
+        assert!(!process_frame(&mut b, 0.0, &sustained));
+
+        // Holding the peak for the whole window trips it.
+        let mut b = Breaker::new();
+        let tripped = (0..window_samples)
+            .map(|_| process_frame(&mut b, THRESHOLD, &sustained))
+            .collect::<Vec<_>>();
+        assert!(!tripped[window_samples - 2]);
+        assert!(tripped[window_samples - 1]);
+
+        // A hot signal under the peak threshold only trips in RMS mode.
+        let hot = THRESHOLD - 1.0;
+        let mut peak_breaker = Breaker::new();
+        let mut rms_breaker = Breaker::new();
+        let mut rms_tripped = false;
+        for _ in 0..(window_samples * 10) {
+            assert!(!process_frame(&mut peak_breaker, hot, &peak));
+            rms_tripped |= process_frame(&mut rms_breaker, hot, &rms);
+        }
+        assert!(rms_tripped);
+    }
+
+    #[test]
+    fn test_protection_modes() {
+        let mut param_values = trip_mode_params(TripMode::Peak, 1.0);
+        let overload = THRESHOLD * 2.0;
+
+        // Muting cuts the signal off.
+        let mut b = Breaker::new();
+        param_values[BreakerParams::PROTECTION] = Protection::Mute as usize as f32;
+        assert_eq!(
+            process_frame_output(&mut b, overload, &param_values),
+            (true, 0.0)
+        );
+
+        // Soft clipping passes the signal through the clipping curve.
+        let mut b = Breaker::new();
+        param_values[BreakerParams::PROTECTION] = Protection::SoftClip as usize as f32;
+        assert_eq!(
+            process_frame_output(&mut b, overload, &param_values),
+            (true, limiter::soft_clip(overload))
+        );
+        let (tripped, quiet) = process_frame_output(&mut b, 1.0, &param_values);
+        assert!(tripped);
+        assert!((quiet - 1.0).abs() < 0.01);
+
+        // Limiting turns the signal down to the limiter's ceiling, but not
+        // off.
+        let mut b = Breaker::new();
+        param_values[BreakerParams::PROTECTION] = Protection::Limit as usize as f32;
+        let (tripped, limited) = (0..1000)
+            .map(|_| process_frame_output(&mut b, overload, &param_values))
+            .last()
+            .unwrap();
+        assert!(tripped);
+        assert!((limited - limiter::CEILING).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_bus_trips_together() {
+        let mut b = Breaker8::new();
+        let mut ins: [Port; 9] = Default::default();
+        let mut outs: [Port; 11] = Default::default();
+        let param_values = trip_mode_params(TripMode::Peak, 1.0);
+        for (index, port) in ins[..8].iter_mut().enumerate() {
+            OutputPort::wrap(port).set_voltages_from_slice(&[index as f32, -1.0]);
+        }
+
+        let tripped = process_unlinked(&mut b, &ins, &mut outs, &param_values);
+        assert!(!tripped);
+        for (input, output) in ins[..8].iter().zip(&outs[1..9]) {
+            assert_eq!(
+                InputPort::wrap(input).as_slice(),
+                InputPort::wrap(output).as_slice()
+            );
+        }
+
+        // Overloading a single input mutes every output.
+        OutputPort::wrap(&mut ins[5]).set_monophonic_voltage(THRESHOLD);
+        let before = trip_time();
+        let tripped = process_unlinked(&mut b, &ins, &mut outs, &param_values);
+        let after = trip_time();
+        assert!(tripped);
+        let gate = InputPort::wrap(&outs[0]).get_monophonic_voltage();
+        assert_eq!(gate, Some(gate::HIGH));
+        for output in &outs[1..9] {
+            let output = InputPort::wrap(output);
+            assert!(output.as_slice().unwrap().iter().all(|v| *v == 0.0));
+        }
+
+        // The trip went into the log.
+        assert_eq!(b.trip_log_snapshot.get_trip_count(), 1);
+        let mut event = TripEvent {
+            time: 0.0,
+            port: 0,
+            channel: 0,
+            level: 0.0,
+            cause: TripCause::Rms,
+        };
+        assert!(b.trip_log_snapshot.get_trip_event(0, &mut event));
+        assert!((before..=after).contains(&event.time));
+        let expected = TripEvent {
+            time: event.time,
+            port: 5,
+            channel: 0,
+            level: THRESHOLD,
+            cause: TripCause::Peak,
+        };
+        assert_eq!(event, expected);
+        assert!(!b.trip_log_snapshot.get_trip_event(1, &mut event));
+
+        // Clearing the log from the UI takes effect on the engine's next frame.
+        b.trip_log_snapshot.request_clear();
+        assert_eq!(b.trip_log.count(), 1);
+        process_unlinked(&mut b, &ins, &mut outs, &param_values);
+        assert_eq!(b.trip_log.count(), 0);
+        assert_eq!(b.trip_log_snapshot.get_trip_log_len(), 0);
+    }
+
+    #[test]
+    fn test_buttons_and_arming() {
+        let mut b = Breaker::new();
+        let mut ins: [Port; 3] = Default::default();
+        let mut outs: [Port; 5] = Default::default();
+        OutputPort::wrap(&mut ins[0]).set_monophonic_voltage(1.0);
+        let mut param_values = trip_mode_params(TripMode::Peak, 1.0);
+        let mut run = |b: &mut Breaker, param_values: &ParamValues| {
+            let tripped = process_unlinked(b, &ins, &mut outs, param_values);
+            let left = InputPort::wrap(&outs[1]).get_monophonic_voltage();
+            (tripped, left.unwrap())
+        };
+        assert_eq!(run(&mut b, &param_values), (false, 1.0));
+
+        // Pressing the trip button opens the breaker, and it stays open after
+        // the button is released.
+        param_values[BreakerParams::TRIP_BUTTON] = 1.0;
+        assert_eq!(run(&mut b, &param_values), (true, 0.0));
+        param_values[BreakerParams::TRIP_BUTTON] = 0.0;
+        assert_eq!(run(&mut b, &param_values), (true, 0.0));
+        assert_eq!(b.trip_log.get(0).unwrap().cause, TripCause::Manual);
+
+        // Disarming lets the signal through, but still reports the trip.
+        param_values[BreakerParams::ARMED] = 0.0;
+        assert_eq!(run(&mut b, &param_values), (true, 1.0));
+
+        // The reset button closes the breaker again.
+        param_values[BreakerParams::ARMED] = 1.0;
+        param_values[BreakerParams::RESET_BUTTON] = 1.0;
+        assert_eq!(run(&mut b, &param_values), (false, 1.0));
+        param_values[BreakerParams::RESET_BUTTON] = 0.0;
+        assert_eq!(run(&mut b, &param_values), (false, 1.0));
+        assert_eq!(b.trip_log_snapshot.get_trip_count(), 1);
+    }
+
+    #[test]
+    fn test_normalling() {
+        let mut b = Breaker::new();
+        let mut ins = [Port::default(), Port::disconnected(), Port::default()];
+        let mut outs: [Port; 5] = Default::default();
+        OutputPort::wrap(&mut ins[0]).set_voltages_from_slice(&[1.0, 2.0, 3.0]);
+        let mut param_values = trip_mode_params(TripMode::Peak, 1.0);
+        let mut run = |b: &mut Breaker, param_values: &ParamValues| {
+            process_unlinked(b, &ins, &mut outs, param_values);
+            let right = InputPort::wrap(&outs[2]);
+            right.as_slice().map(Vec::from)
+        };
+
+        // Without normalling, an unpatched Right input gives a silent output.
+        assert_eq!(run(&mut b, &param_values), Some(vec![0.0]));
+
+        // With it, Right follows Left, polyphony and all.
+        param_values[BreakerParams::NORMAL] = 1.0;
+        assert_eq!(run(&mut b, &param_values), Some(vec![1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn test_trip_and_reset_triggers() {
+        let mut b = Breaker::new();
+        let ins: [Port; 3] = Default::default();
+        let mut outs: [Port; 5] = Default::default();
+        let mut param_values = trip_mode_params(TripMode::Peak, 1.0);
+        // Run some frames, returning how many had the trip and reset triggers
+        // high.
+        let mut run = |b: &mut Breaker, param_values: &ParamValues, frames: usize| {
+            let mut counts = (0, 0);
+            for _ in 0..frames {
+                process_unlinked(b, &ins, &mut outs, param_values);
+                let high = |port: &Port| {
+                    InputPort::wrap(port).get_monophonic_voltage() == Some(gate::HIGH)
+                };
+                counts.0 += high(&outs[3]) as usize;
+                counts.1 += high(&outs[4]) as usize;
+            }
+            counts
+        };
+        // A 1 ms trigger at 44.1 kHz.
+        let trigger_frames = 45;
+
+        assert_eq!(run(&mut b, &param_values, 100), (0, 0));
+
+        // Holding the trip button down only fires one trigger.
+        param_values[BreakerParams::TRIP_BUTTON] = 1.0;
+        assert_eq!(run(&mut b, &param_values, 100), (trigger_frames, 0));
+        param_values[BreakerParams::TRIP_BUTTON] = 0.0;
+        assert_eq!(run(&mut b, &param_values, 100), (0, 0));
+
+        param_values[BreakerParams::RESET_BUTTON] = 1.0;
+        assert_eq!(run(&mut b, &param_values, 100), (0, trigger_frames));
+
+        // Resetting a breaker that's already closed doesn't fire again.
+        param_values[BreakerParams::RESET_BUTTON] = 0.0;
+        run(&mut b, &param_values, 1);
+        param_values[BreakerParams::RESET_BUTTON] = 1.0;
+        assert_eq!(run(&mut b, &param_values, 100), (0, 0));
+    }
+
+    #[test]
+    fn test_linked_group() {
+        let mut breakers: [Breaker; 3] = Default::default();
+        let ins: [Port; 3] = Default::default();
+        let mut outs: [Port; 5] = Default::default();
+        // Messages each breaker sent to the left and right on the last frame.
+        let mut sent = [(BreakerLinkMessage::default(), BreakerLinkMessage::default()); 3];
+        // Run a frame of the whole row, with `pressed` holding down one
+        // breaker's trip or reset button, returning which breakers are open.
+        let mut run =
+            |breakers: &mut [Breaker; 3], linked: bool, pressed: Option<(usize, usize)>| {
+                let received = sent;
+                let mut tripped = [false; 3];
+                for (i, b) in breakers.iter_mut().enumerate() {
+                    let mut values = trip_mode_params(TripMode::Peak, 1.0);
+                    values[BreakerParams::LINK] = linked as u8 as f32;
+                    if let Some((button, index)) = pressed {
+                        values[button] = (index == i) as u8 as f32;
+                    }
+                    let values = values.map(Param::new);
+                    let params = BreakerParams::from_raw_ptr(values.as_ptr());
+                    let (mut to_left, mut to_right) = Default::default();
+                    let mut expanders = Expanders {
+                        from_left: i.checked_sub(1).map(|left| &received[left].1),
+                        from_right: received.get(i + 1).map(|right| &right.0),
+                        to_left: (i > 0).then_some(&mut to_left),
+                        to_right: (i < 2).then_some(&mut to_right),
+                    };
+                    let inputs = BreakerInputs::from_raw_ptr(ins.as_ptr());
+                    let mut outputs = BreakerOutputs::from_raw_ptr(outs.as_mut_ptr());
+                    b.process(
+                        &inputs,
+                        &mut outputs,
+                        &params,
+                        &mut expanders,
+                        &mut tripped[i],
+                    );
+                    sent[i] = (to_left, to_right);
+                }
+                tripped
+            };
+
+        // A trip in the middle spreads one breaker further each frame.
+        assert_eq!(
+            run(&mut breakers, true, Some((BreakerParams::TRIP_BUTTON, 1))),
+            [false, true, false]
+        );
+        assert_eq!(run(&mut breakers, true, None), [true, true, true]);
+        assert_eq!(
+            breakers[0].trip_log.get(0).unwrap().cause,
+            TripCause::Linked
+        );
+        assert_eq!(
+            breakers[1].trip_log.get(0).unwrap().cause,
+            TripCause::Manual
+        );
+        // Nothing keeps bouncing around once it's spread.
+        for _ in 0..10 {
+            run(&mut breakers, true, None);
+        }
+        assert_eq!(breakers[2].trip_log_snapshot.get_trip_count(), 1);
+
+        // So does a reset from the end of the row.
+        assert_eq!(
+            run(&mut breakers, true, Some((BreakerParams::RESET_BUTTON, 0))),
+            [false, true, true]
+        );
+        assert_eq!(run(&mut breakers, true, None), [false, false, true]);
+        assert_eq!(run(&mut breakers, true, None), [false, false, false]);
+
+        // Without linking, breakers trip alone.
+        run(&mut breakers, false, Some((BreakerParams::TRIP_BUTTON, 1)));
+        for _ in 0..10 {
+            assert_eq!(run(&mut breakers, false, None), [false, true, false]);
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use crate::ffi::bridge::TripCause;
+use crate::rack::PORT_MAX_CHANNELS;
+
+pub const THRESHOLD: f32 = 12.0;
+
+const DEFAULT_WINDOW_MS: f32 = 50.0;
+
+// The RMS level of a sine wave peaking at THRESHOLD. Anything that averages
+// out hotter than this for the length of the window is considered too loud.
+const RMS_THRESHOLD: f32 = THRESHOLD * std::f32::consts::FRAC_1_SQRT_2;
+
+// Time constant of the low-pass filter tracking each channel's DC offset.
+const DC_TIME_CONSTANT_MS: f32 = 200.0;
+
+// Level growth is judged by comparing the peak of each block of samples to
+// the one before it. A block counts as growing if its peak beats the previous
+// block's by the block length's share of the growth time, so the level has to
+// keep rising by a factor of about e (8.7 dB) every growth time. A rise that
+// starts from 0 V and never speeds up, like a ramp, an envelope's attack or a
+// quarter cycle of an LFO, falls under that rate before the growth time is
+// up, however fast or slow it is. The margin also keeps a steady signal's
+// sampling jitter from counting.
+const GROWTH_BLOCK_MS: f32 = 10.0;
+// Blocks quieter than this never count as growing, so that fades and
+// envelopes at low levels don't trip the breaker.
+const GROWTH_FLOOR: f32 = 1.0;
+
+// Selects the criterion used to decide when the breaker trips. The
+// discriminants match the values of the trip mode param on the C++ side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TripMode {
+    // Trip as soon as any single sample reaches THRESHOLD.
+    #[default]
+    Peak,
+    // Trip when the RMS level over the window reaches RMS_THRESHOLD.
+    Rms,
+    // Trip when a channel stays at or above THRESHOLD for the whole window.
+    SustainedPeak,
+}
+
+impl TripMode {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => TripMode::Rms,
+            2 => TripMode::SustainedPeak,
+            _ => TripMode::Peak,
+        }
+    }
+}
+
+// Which channel of a port tripped the detector, and why. The level is
+// whatever the criterion measured: a sample value for the peak criteria, an
+// RMS level, a (signed) DC offset, or the peak of a growth block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Detection {
+    pub channel: usize,
+    pub level: f32,
+    pub cause: TripCause,
+}
+
+// Everything the detector needs to know from the module's params and the
+// engine. The derived values in DetectorSettings are only recomputed when
+// this changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DetectorConfig {
+    pub mode: TripMode,
+    pub window_ms: f32,
+    // The DC offset detector is disabled if this is None.
+    pub dc_limit: Option<f32>,
+    // The runaway growth detector is disabled if this is None.
+    pub growth_ms: Option<f32>,
+    // The slew rate limit in V/ms. The slew rate detector is disabled if this
+    // is None.
+    pub slew_limit: Option<f32>,
+    pub sample_rate: f32,
+}
+
+// Per-sample constants derived from a DetectorConfig.
+struct DetectorSettings {
+    config: DetectorConfig,
+    // The window length converted to samples at the current sample rate.
+    window_samples: u32,
+    // One-pole smoothing coefficient for the running mean square. Its time
+    // constant is the window length.
+    rms_coefficient: f32,
+    dc_coefficient: f32,
+    block_samples: u32,
+    // How many growing blocks in a row it takes to trip, if enabled.
+    growth_blocks: Option<u32>,
+    // How much louder than the last block a block has to be to count as
+    // growing.
+    growth_ratio: f32,
+    // The slew rate limit converted to volts per sample, if enabled.
+    slew_limit_per_sample: Option<f32>,
+}
+
+impl DetectorSettings {
+    fn new(config: DetectorConfig) -> Self {
+        let samples_per_ms = 0.001 * config.sample_rate;
+        let window_samples = (config.window_ms * samples_per_ms).max(1.0);
+        let dc_samples = DC_TIME_CONSTANT_MS * samples_per_ms;
+        let block_samples = (GROWTH_BLOCK_MS * samples_per_ms).round().max(1.0);
+        let growth_blocks = config
+            .growth_ms
+            .map(|ms| (ms / GROWTH_BLOCK_MS).round().max(1.0) as u32);
+        let growth_ratio = 1.0 + growth_blocks.map_or(0.0, |blocks| (blocks as f32).recip());
+        let slew_limit_per_sample = config.slew_limit.map(|limit| limit / samples_per_ms);
+        DetectorSettings {
+            config,
+            window_samples: window_samples.round() as u32,
+            rms_coefficient: one_pole_coefficient(window_samples),
+            dc_coefficient: one_pole_coefficient(dc_samples),
+            block_samples: block_samples as u32,
+            growth_blocks,
+            growth_ratio,
+            slew_limit_per_sample,
+        }
+    }
+}
+
+// The smoothing coefficient for a one-pole low-pass filter with a time
+// constant of `samples`.
+pub fn one_pole_coefficient(samples: f32) -> f32 {
+    1.0 - (-1.0 / samples.max(1.0)).exp()
+}
+
+// Per-channel state for the criteria that look at more than one sample.
+#[derive(Clone, Copy, Default)]
+struct ChannelLevel {
+    mean_square: f32,
+    samples_over: u32,
+    dc_mean: f32,
+    block_peak: f32,
+    previous_block_peak: f32,
+    growing_blocks: u32,
+    // None until the channel has seen its first sample, so that a signal
+    // that starts away from 0 V isn't mistaken for a jump.
+    previous_value: Option<f32>,
+}
+
+// Level history for all the channels of one input port.
+#[derive(Default)]
+struct PortLevels {
+    channels: [ChannelLevel; PORT_MAX_CHANNELS],
+    // How far we are into the current growth block.
+    block_position: u32,
+}
+
+impl PortLevels {
+    // Process one frame, returning the first channel that tripped, if any.
+    // Every criterion still sees every channel, to keep their history intact.
+    fn process(&mut self, settings: &DetectorSettings, values: &[f32]) -> Option<Detection> {
+        let states = &mut self.channels[..values.len()];
+        let mut detection = None;
+        let mut detect = |channel: usize, level: f32, cause: TripCause| {
+            detection.get_or_insert(Detection {
+                channel,
+                level,
+                cause,
+            });
+        };
+
+        match settings.config.mode {
+            TripMode::Peak => {
+                if let Some(channel) = out_of_range(values, THRESHOLD) {
+                    detect(channel, values[channel].abs(), TripCause::Peak);
+                }
+            }
+            TripMode::Rms => {
+                let coefficient = settings.rms_coefficient;
+                let limit = RMS_THRESHOLD * RMS_THRESHOLD;
+                for (channel, (state, value)) in states.iter_mut().zip(values).enumerate() {
+                    // As with out_of_range(), NaNs are let through. Skipping
+                    // them here also keeps them from poisoning the average.
+                    if value.is_nan() {
+                        continue;
+                    }
+                    state.mean_square += (value * value - state.mean_square) * coefficient;
+                    if state.mean_square >= limit {
+                        detect(channel, state.mean_square.sqrt(), TripCause::Rms);
+                    }
+                }
+            }
+            TripMode::SustainedPeak => {
+                let window_samples = settings.window_samples;
+                for (channel, (state, value)) in states.iter_mut().zip(values).enumerate() {
+                    if value.abs() >= THRESHOLD {
+                        state.samples_over = state.samples_over.saturating_add(1);
+                    } else {
+                        state.samples_over = 0;
+                    }
+                    if state.samples_over >= window_samples {
+                        detect(channel, value.abs(), TripCause::SustainedPeak);
+                    }
+                }
+            }
+        }
+
+        if let Some(dc_limit) = settings.config.dc_limit {
+            let coefficient = settings.dc_coefficient;
+            for (channel, (state, value)) in states.iter_mut().zip(values).enumerate() {
+                if value.is_nan() {
+                    continue;
+                }
+                state.dc_mean += (value - state.dc_mean) * coefficient;
+                if state.dc_mean.abs() >= dc_limit {
+                    detect(channel, state.dc_mean, TripCause::DcOffset);
+                }
+            }
+        }
+
+        if let Some(growth_blocks) = settings.growth_blocks {
+            for (state, value) in states.iter_mut().zip(values) {
+                // f32::max() ignores NaNs for us.
+                state.block_peak = state.block_peak.max(value.abs());
+            }
+            self.block_position += 1;
+            if self.block_position >= settings.block_samples {
+                self.block_position = 0;
+                let ratio = settings.growth_ratio;
+                for (channel, state) in states.iter_mut().enumerate() {
+                    let growing = state.block_peak >= GROWTH_FLOOR
+                        && state.block_peak > state.previous_block_peak * ratio;
+                    if growing {
+                        state.growing_blocks = state.growing_blocks.saturating_add(1);
+                    } else {
+                        state.growing_blocks = 0;
+                    }
+                    if state.growing_blocks >= growth_blocks {
+                        detect(channel, state.block_peak, TripCause::Growth);
+                    }
+                    state.previous_block_peak = state.block_peak;
+                    state.block_peak = 0.0;
+                }
+            }
+        }
+
+        if let Some(limit) = settings.slew_limit_per_sample {
+            let samples_per_ms = 0.001 * settings.config.sample_rate;
+            for (channel, (state, value)) in states.iter_mut().zip(values).enumerate() {
+                if value.is_nan() {
+                    continue;
+                }
+                if let Some(previous) = state.previous_value {
+                    let change = (value - previous).abs();
+                    if change >= limit {
+                        detect(channel, change * samples_per_ms, TripCause::Slew);
+                    }
+                }
+                state.previous_value = Some(*value);
+            }
+        }
+
+        detection
+    }
+}
+
+// Tracks signal levels over time to decide when the breaker should trip.
+// There is one set of channel states for each of the N input ports we watch.
+pub struct LevelDetector<const N: usize> {
+    settings: DetectorSettings,
+    ports: [PortLevels; N],
+}
+
+impl<const N: usize> LevelDetector<N> {
+    pub fn new(sample_rate: f32) -> Self {
+        let config = DetectorConfig {
+            mode: TripMode::default(),
+            window_ms: DEFAULT_WINDOW_MS,
+            dc_limit: None,
+            growth_ms: None,
+            slew_limit: None,
+            sample_rate,
+        };
+        LevelDetector {
+            settings: DetectorSettings::new(config),
+            ports: core::array::from_fn(|_| PortLevels::default()),
+        }
+    }
+
+    // Apply the current settings, recomputing the derived values if anything
+    // has changed. Changing which criteria are in use starts from a clean
+    // slate.
+    pub fn configure(&mut self, config: DetectorConfig) {
+        let old_config = self.settings.config;
+        if config != old_config {
+            self.settings = DetectorSettings::new(config);
+            let criteria_changed = config.mode != old_config.mode
+                || config.dc_limit.is_some() != old_config.dc_limit.is_some()
+                || config.growth_ms.is_some() != old_config.growth_ms.is_some()
+                || config.slew_limit.is_some() != old_config.slew_limit.is_some();
+            if criteria_changed {
+                self.reset();
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.ports.fill_with(PortLevels::default);
+    }
+
+    // Feed one frame of an input port through the detector, returning the
+    // reason for tripping the breaker, if any. A disconnected port clears its
+    // state.
+    pub fn process_port(&mut self, port_index: usize, values: Option<&[f32]>) -> Option<Detection> {
+        let port = &mut self.ports[port_index];
+        let values = values.unwrap_or_default();
+        port.channels[values.len()..].fill(ChannelLevel::default());
+        port.process(&self.settings, values)
+    }
+}
+
+// Find the first channel with a value out of range, if any.
+fn out_of_range(values: &[f32], threshold: f32) -> Option<usize> {
+    // Note that depending on exactly how we do this comparison, NaNs may or may
+    // not trigger it. Currently we opt to let NaNs through without triggering.
+    values.iter().position(|n| n.abs() >= threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn detector(dc_limit: Option<f32>, growth_ms: Option<f32>) -> LevelDetector<1> {
+        slew_detector(dc_limit, growth_ms, None)
+    }
+
+    fn slew_detector(
+        dc_limit: Option<f32>,
+        growth_ms: Option<f32>,
+        slew_limit: Option<f32>,
+    ) -> LevelDetector<1> {
+        let mut d = LevelDetector::new(SAMPLE_RATE);
+        d.configure(DetectorConfig {
+            mode: TripMode::Peak,
+            window_ms: DEFAULT_WINDOW_MS,
+            dc_limit,
+            growth_ms,
+            slew_limit,
+            sample_rate: SAMPLE_RATE,
+        });
+        d
+    }
+
+    // Run `seconds` worth of a signal through the detector, returning the
+    // time in seconds at which it tripped, if it did.
+    fn time_to_trip(d: &mut LevelDetector<1>, seconds: f32, f: impl Fn(f32) -> f32) -> Option<f32> {
+        let frames = (seconds * SAMPLE_RATE) as usize;
+        (0..frames).find_map(|frame| {
+            let t = frame as f32 / SAMPLE_RATE;
+            d.process_port(0, Some(&[f(t)])).map(|_| t)
+        })
+    }
+
+    fn sine(t: f32, hz: f32) -> f32 {
+        (std::f32::consts::TAU * hz * t).sin()
+    }
+
+    #[test]
+    fn test_dc_offset() {
+        // A large offset trips once the tracked mean catches up to it.
+        let mut d = detector(Some(2.0), None);
+        let t = time_to_trip(&mut d, 1.0, |t| 3.0 + 5.0 * sine(t, 100.0));
+        assert!(t.is_some_and(|t| t > 0.1));
+
+        // Large AC swings around zero don't.
+        let mut d = detector(Some(2.0), None);
+        assert_eq!(time_to_trip(&mut d, 1.0, |t| 10.0 * sine(t, 100.0)), None);
+
+        // Nor does an offset when the detector is disabled.
+        let mut d = detector(None, None);
+        assert_eq!(time_to_trip(&mut d, 1.0, |_| 5.0), None);
+    }
+
+    #[test]
+    fn test_runaway_growth() {
+        // An oscillation doubling in level every quarter second, starting
+        // well under the threshold.
+        let runaway = |t: f32| 0.25 * 2.0f32.powf(4.0 * t) * sine(t, 220.0);
+        let mut d = detector(None, Some(500.0));
+        let t = time_to_trip(&mut d, 2.0, runaway);
+        assert!(t.is_some_and(|t| (0.9..1.2).contains(&t)));
+
+        // A loud but steady oscillation doesn't trip.
+        let mut d = detector(None, Some(500.0));
+        assert_eq!(time_to_trip(&mut d, 2.0, |t| 10.0 * sine(t, 220.0)), None);
+
+        // With the growth detector disabled, it only trips once it reaches
+        // the peak threshold.
+        let mut d = detector(None, None);
+        let t = time_to_trip(&mut d, 3.0, runaway);
+        assert!(t.is_some_and(|t| t > 1.3));
+    }
+
+    #[test]
+    fn test_slow_rises_dont_look_like_growth() {
+        // A slow LFO's level rises for a quarter of every cycle, and an
+        // envelope's for its whole attack, but neither should trip, whatever
+        // the growth time.
+        type Signal = fn(f32) -> f32;
+        let rises: [(&str, Signal); 4] = [
+            ("0.5 Hz sine", |t| 10.0 * sine(t, 0.5)),
+            ("0.1 Hz sine", |t| 10.0 * sine(t, 0.1)),
+            ("2 s ramp", |t| 5.0 * t.min(2.0)),
+            ("1 s attack", |t| 10.0 * (1.0 - (-t).exp())),
+        ];
+        for (name, rise) in rises {
+            for growth_ms in [100.0, 500.0, 5000.0] {
+                let mut d = detector(None, Some(growth_ms));
+                let t = time_to_trip(&mut d, 10.0, rise);
+                assert_eq!(t, None, "{name} tripped with {growth_ms} ms growth time");
+            }
+        }
+    }
+
+    #[test]
+    fn test_slew_rate() {
+        // Sampled at 48 kHz, a 2 V sine at 20 kHz changes by up to ~185 V/ms
+        // between samples, which a 100 V/ms limit catches even though it's
+        // nowhere near the peak threshold.
+        let mut d = slew_detector(None, None, Some(100.0));
+        assert!(time_to_trip(&mut d, 0.1, |t| 2.0 * sine(t, 20000.0)).is_some());
+
+        // A 10 V sine at 1 kHz (~63 V/ms) doesn't trip, and neither does
+        // starting out at a large DC offset.
+        let mut d = slew_detector(None, None, Some(100.0));
+        assert_eq!(time_to_trip(&mut d, 0.1, |t| 10.0 * sine(t, 1000.0)), None);
+        let mut d = slew_detector(None, None, Some(100.0));
+        assert_eq!(time_to_trip(&mut d, 0.1, |_| 10.0), None);
+    }
+}
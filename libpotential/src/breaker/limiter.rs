@@ -0,0 +1,136 @@
+use super::detector::one_pole_coefficient;
+
+// The level the limiter and soft clipper hold signals under once the breaker
+// has tripped. This is a little under the trip threshold so that limited
+// signals have some headroom before anything downstream clips.
+pub const CEILING: f32 = 10.0;
+
+// What the breaker does to its signals once it has tripped. The discriminants
+// match the values of the protection param on the C++ side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protection {
+    // Cut the signals off entirely.
+    #[default]
+    Mute,
+    // Turn the signals down with a peak limiter.
+    Limit,
+    // Round off anything approaching CEILING with a tanh curve.
+    SoftClip,
+}
+
+impl Protection {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Protection::Limit,
+            2 => Protection::SoftClip,
+            _ => Protection::Mute,
+        }
+    }
+}
+
+pub fn soft_clip(value: f32) -> f32 {
+    CEILING * (value / CEILING).tanh()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LimiterConfig {
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub sample_rate: f32,
+}
+
+// A feed-forward peak limiter with a single gain shared by every channel it
+// processes, so that the balance between them is kept.
+pub struct Limiter {
+    config: LimiterConfig,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    pub const DEFAULT_ATTACK_MS: f32 = 1.0;
+    pub const DEFAULT_RELEASE_MS: f32 = 100.0;
+
+    pub fn new(sample_rate: f32) -> Self {
+        let mut limiter = Limiter {
+            config: LimiterConfig {
+                attack_ms: Self::DEFAULT_ATTACK_MS,
+                release_ms: Self::DEFAULT_RELEASE_MS,
+                sample_rate,
+            },
+            attack_coefficient: 1.0,
+            release_coefficient: 1.0,
+            gain: 1.0,
+        };
+        limiter.update_coefficients();
+        limiter
+    }
+
+    pub fn configure(&mut self, config: LimiterConfig) {
+        if config != self.config {
+            self.config = config;
+            self.update_coefficients();
+        }
+    }
+
+    fn update_coefficients(&mut self) {
+        let samples_per_ms = 0.001 * self.config.sample_rate;
+        self.attack_coefficient = one_pole_coefficient(self.config.attack_ms * samples_per_ms);
+        self.release_coefficient = one_pole_coefficient(self.config.release_ms * samples_per_ms);
+    }
+
+    pub fn reset(&mut self) {
+        self.gain = 1.0;
+    }
+
+    // Update the gain for a frame whose loudest channel is at `peak`, and
+    // return the gain to apply to it.
+    pub fn process(&mut self, peak: f32) -> f32 {
+        let target = if peak > CEILING { CEILING / peak } else { 1.0 };
+        let coefficient = if target < self.gain {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.gain += (target - self.gain) * coefficient;
+        self.gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter() {
+        let sample_rate = 48000.0;
+        let mut limiter = Limiter::new(sample_rate);
+
+        // Quiet signals are left alone.
+        assert_eq!(limiter.process(5.0), 1.0);
+
+        // A loud one is brought down to the ceiling within a few attack times.
+        let attack_samples = (Limiter::DEFAULT_ATTACK_MS * 0.001 * sample_rate) as usize;
+        let gain = (0..attack_samples * 10)
+            .map(|_| limiter.process(20.0))
+            .last()
+            .unwrap();
+        assert!((gain * 20.0 - CEILING).abs() < 0.01);
+
+        // And the gain recovers more slowly once it goes away.
+        let gain = (0..attack_samples * 10)
+            .map(|_| limiter.process(0.0))
+            .last()
+            .unwrap();
+        assert!(gain < 0.9);
+    }
+
+    #[test]
+    fn test_soft_clip() {
+        assert_eq!(soft_clip(0.0), 0.0);
+        assert!((soft_clip(1.0) - 1.0).abs() < 0.01);
+        assert!(soft_clip(100.0) <= CEILING);
+        assert_eq!(soft_clip(-20.0), -soft_clip(20.0));
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::ffi::bridge::TripEvent;
+
+// The number of recent trips we keep the details of.
+const TRIP_LOG_LENGTH: usize = 8;
+
+// Trip statistics for a breaker: how many times it has tripped, and the
+// details of the most recent trips.
+//
+// The events live in a fixed-size ring buffer rather than a growable
+// collection, so recording a trip never allocates on the engine thread, and
+// the whole log can be copied into a TripLogSnapshot.
+#[derive(Clone, Copy, Default)]
+pub struct TripLog {
+    count: u64,
+    events: [Option<TripEvent>; TRIP_LOG_LENGTH],
+    // The slot the next event will be written to.
+    next: usize,
+}
+
+impl TripLog {
+    pub fn record(&mut self, event: TripEvent) {
+        self.count = self.count.saturating_add(1);
+        self.push(event);
+    }
+
+    // Add an event to the log without counting it as a new trip. This is for
+    // restoring a saved log, oldest event first.
+    pub fn push(&mut self, event: TripEvent) {
+        self.events[self.next] = Some(event);
+        self.next = (self.next + 1) % TRIP_LOG_LENGTH;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn set_count(&mut self, count: u64) {
+        self.count = count;
+    }
+
+    // The number of events currently held in the log.
+    pub fn len(&self) -> usize {
+        self.events.iter().flatten().count()
+    }
+
+    // Get an event by age, where 0 is the most recent trip.
+    pub fn get(&self, index: usize) -> Option<TripEvent> {
+        if index >= TRIP_LOG_LENGTH {
+            return None;
+        }
+        let slot = (self.next + TRIP_LOG_LENGTH - 1 - index) % TRIP_LOG_LENGTH;
+        self.events[slot]
+    }
+
+    pub fn clear(&mut self) {
+        *self = TripLog::default();
+    }
+}
+
+// The copy of a breaker's trip log that the UI and patch saving read from
+// their own threads. The engine thread owns the log itself and publishes it
+// here whenever it changes. The UI can't clear the log directly either, so it
+// asks the engine to do it on its next frame.
+#[derive(Default)]
+pub struct TripLogSnapshot {
+    log: Mutex<TripLog>,
+    clear_requested: AtomicBool,
+}
+
+impl TripLogSnapshot {
+    // Copy the engine's log into the snapshot, unless another thread is
+    // reading it right now. Returns whether the log was published; if not,
+    // try again later rather than block the engine.
+    pub fn publish(&self, log: &TripLog) -> bool {
+        match self.log.try_lock() {
+            Ok(mut snapshot) => {
+                *snapshot = *log;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Returns whether the UI asked for the log to be cleared since the last
+    // call.
+    pub fn take_clear_request(&self) -> bool {
+        self.clear_requested.swap(false, Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TripLog> {
+        // The log is plain data, so it's still fine to read if a thread
+        // panicked while holding the lock.
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get_trip_count(&self) -> u64 {
+        self.lock().count()
+    }
+
+    pub fn get_trip_log_len(&self) -> usize {
+        self.lock().len()
+    }
+
+    // Copy out a trip log event by age (0 is the most recent), returning false
+    // if there's no such event.
+    pub fn get_trip_event(&self, index: usize, event: &mut TripEvent) -> bool {
+        self.lock().get(index).map(|e| *event = e).is_some()
+    }
+
+    // Ask the engine to clear the log. The snapshot is cleared straight away,
+    // so that the menu shows it.
+    pub fn request_clear(&self) {
+        self.clear_requested.store(true, Ordering::Relaxed);
+        self.lock().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::bridge::TripCause;
+
+    fn event(time: f64) -> TripEvent {
+        TripEvent {
+            time,
+            port: 0,
+            channel: 0,
+            level: 12.0,
+            cause: TripCause::Peak,
+        }
+    }
+
+    #[test]
+    fn test_trip_log() {
+        let mut log = TripLog::default();
+        assert_eq!(log.len(), 0);
+        assert_eq!(log.get(0), None);
+
+        for time in 0..10 {
+            log.record(event(time as f64));
+        }
+        assert_eq!(log.count(), 10);
+        assert_eq!(log.len(), TRIP_LOG_LENGTH);
+        // The most recent trips come first, and the oldest have been dropped.
+        let times: Vec<_> = (0..TRIP_LOG_LENGTH)
+            .map(|index| log.get(index).unwrap().time)
+            .collect();
+        assert_eq!(times, [9.0, 8.0, 7.0, 6.0, 5.0, 4.0, 3.0, 2.0]);
+        assert_eq!(log.get(TRIP_LOG_LENGTH), None);
+
+        log.clear();
+        assert_eq!(log.count(), 0);
+        assert_eq!(log.len(), 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut log = TripLog::default();
+        let snapshot = TripLogSnapshot::default();
+        log.record(event(1.0));
+        log.record(event(2.0));
+        assert!(snapshot.publish(&log));
+        assert_eq!(snapshot.get_trip_count(), 2);
+        assert_eq!(snapshot.get_trip_log_len(), 2);
+        let mut latest = event(0.0);
+        assert!(snapshot.get_trip_event(0, &mut latest));
+        assert_eq!(latest.time, 2.0);
+        assert!(!snapshot.get_trip_event(2, &mut latest));
+
+        // The engine can't publish while the log is being read.
+        {
+            let _reading = snapshot.lock();
+            assert!(!snapshot.publish(&log));
+        }
+
+        assert!(!snapshot.take_clear_request());
+        snapshot.request_clear();
+        assert_eq!(snapshot.get_trip_count(), 0);
+        assert!(snapshot.take_clear_request());
+        assert!(!snapshot.take_clear_request());
+    }
+}
@@ -1,35 +1,151 @@
 use crate::module_config::ModuleConfigInfo;
-use crate::rack::Port;
+use crate::rack::{Param, Port};
 
+// The process functions take a pointer for each of Rack's buffers, which adds
+// up to more arguments than clippy would like.
+#[allow(clippy::too_many_arguments)]
 #[cxx::bridge(namespace = "rustlib")]
 pub mod bridge {
+    // The criterion that tripped a breaker. Patches save these by name.
+    #[derive(Debug)]
+    enum TripCause {
+        Peak,
+        Rms,
+        SustainedPeak,
+        DcOffset,
+        Growth,
+        Manual,
+        Slew,
+        Linked,
+    }
+
+    // A single entry in a breaker's trip log. The time is when the trip
+    // happened, in seconds since the Unix epoch, and port and channel identify
+    // the offending input.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct TripEvent {
+        time: f64,
+        port: usize,
+        channel: usize,
+        level: f32,
+        cause: TripCause,
+    }
+
+    // The expander message linked Breakers pass to their neighbours, carrying
+    // trip and reset events along the row.
+    #[derive(Clone, Copy, Debug, Default)]
+    struct BreakerLinkMessage {
+        trip: bool,
+        reset: bool,
+    }
+
     extern "Rust" {
         type Port;
+        type Param;
 
         type ModuleConfigInfo;
         fn get_input_port_count(self: &ModuleConfigInfo) -> usize;
         fn get_input_port_name(self: &ModuleConfigInfo, index: usize) -> *const c_char;
         fn get_output_port_count(self: &ModuleConfigInfo) -> usize;
         fn get_output_port_name(self: &ModuleConfigInfo, index: usize) -> *const c_char;
+        fn get_param_count(self: &ModuleConfigInfo) -> usize;
+        fn get_param_name(self: &ModuleConfigInfo, index: usize) -> *const c_char;
+        fn get_param_min(self: &ModuleConfigInfo, index: usize) -> f32;
+        fn get_param_max(self: &ModuleConfigInfo, index: usize) -> f32;
+        fn get_param_default(self: &ModuleConfigInfo, index: usize) -> f32;
+        fn get_param_unit(self: &ModuleConfigInfo, index: usize) -> *const c_char;
+        fn get_param_display_base(self: &ModuleConfigInfo, index: usize) -> f32;
+        fn get_param_is_button(self: &ModuleConfigInfo, index: usize) -> bool;
+        fn get_param_label_count(self: &ModuleConfigInfo, index: usize) -> usize;
+        fn get_param_label(self: &ModuleConfigInfo, index: usize, label: usize) -> *const c_char;
         unsafe fn module_config_free(ptr: *mut ModuleConfigInfo);
 
+        type TripLogSnapshot;
+        fn get_trip_count(self: &TripLogSnapshot) -> u64;
+        fn get_trip_log_len(self: &TripLogSnapshot) -> usize;
+        fn get_trip_event(self: &TripLogSnapshot, index: usize, event: &mut TripEvent) -> bool;
+        fn request_clear(self: &TripLogSnapshot);
+
         type Breaker;
         unsafe fn process_raw(
             self: &mut Breaker,
             inputs: *const Port,
             outputs: *mut Port,
+            params: *const Param,
+            from_left: *const BreakerLinkMessage,
+            from_right: *const BreakerLinkMessage,
+            to_left: *mut BreakerLinkMessage,
+            to_right: *mut BreakerLinkMessage,
             tripped_status: &mut bool,
         );
+        fn set_sample_rate(self: &mut Breaker, sample_rate: f32);
+        fn get_trip_log(self: &Breaker) -> *const TripLogSnapshot;
+        fn set_trip_count(self: &mut Breaker, count: u64);
+        fn restore_trip_event(self: &mut Breaker, event: TripEvent);
+        fn clear_trip_log(self: &mut Breaker);
         fn get_module_config_info(self: &mut Breaker) -> *mut ModuleConfigInfo;
         fn breaker_new() -> *mut Breaker;
         unsafe fn breaker_free(ptr: *mut Breaker);
 
-        unsafe fn mag_sign_process_raw(inputs: *const Port, outputs: *mut Port);
+        type Breaker8;
+        unsafe fn process_raw(
+            self: &mut Breaker8,
+            inputs: *const Port,
+            outputs: *mut Port,
+            params: *const Param,
+            from_left: *const BreakerLinkMessage,
+            from_right: *const BreakerLinkMessage,
+            to_left: *mut BreakerLinkMessage,
+            to_right: *mut BreakerLinkMessage,
+            tripped_status: &mut bool,
+        );
+        fn set_sample_rate(self: &mut Breaker8, sample_rate: f32);
+        fn get_trip_log(self: &Breaker8) -> *const TripLogSnapshot;
+        fn set_trip_count(self: &mut Breaker8, count: u64);
+        fn restore_trip_event(self: &mut Breaker8, event: TripEvent);
+        fn clear_trip_log(self: &mut Breaker8);
+        fn get_module_config_info(self: &mut Breaker8) -> *mut ModuleConfigInfo;
+        fn breaker8_new() -> *mut Breaker8;
+        unsafe fn breaker8_free(ptr: *mut Breaker8);
+
+        type MagSign;
+        unsafe fn process_raw(
+            self: &mut MagSign,
+            inputs: *const Port,
+            outputs: *mut Port,
+            params: *const Param,
+        );
+        fn set_sample_rate(self: &mut MagSign, sample_rate: f32);
+        fn get_module_config_info(self: &MagSign) -> *mut ModuleConfigInfo;
+        fn mag_sign_new() -> *mut MagSign;
+        unsafe fn mag_sign_free(ptr: *mut MagSign);
+
+        type PolyShuffleSnapshot;
+        fn get_seed(self: &PolyShuffleSnapshot) -> u64;
+        fn get_position(self: &PolyShuffleSnapshot) -> u64;
+        fn get_destination(self: &PolyShuffleSnapshot, channel: usize) -> usize;
+        fn request_seed(self: &PolyShuffleSnapshot, seed: u64);
 
         type PolyShuffle;
-        unsafe fn process_raw(self: &mut PolyShuffle, inputs: *const Port, outputs: *mut Port);
+        unsafe fn process_raw(
+            self: &mut PolyShuffle,
+            inputs: *const Port,
+            outputs: *mut Port,
+            params: *const Param,
+        );
+        fn get_snapshot(self: &PolyShuffle) -> *const PolyShuffleSnapshot;
+        fn restore_state(
+            self: &mut PolyShuffle,
+            seed: u64,
+            position: u64,
+            destinations: &[usize],
+            module_id: i64,
+        );
+        fn on_add(self: &mut PolyShuffle, module_id: i64, entropy: u64);
         fn get_module_config_info(self: &PolyShuffle) -> *mut ModuleConfigInfo;
         fn polyshuffle_new() -> *mut PolyShuffle;
+        fn polyshuffle_new_with_entropy(entropy: u64) -> *mut PolyShuffle;
+        fn polyshuffle_default_seed() -> u64;
         unsafe fn polyshuffle_free(ptr: *mut PolyShuffle);
     }
 }
@@ -55,20 +171,38 @@ pub fn module_config_free(ptr: *mut ModuleConfigInfo) {
     drop_raw(ptr)
 }
 
-use crate::breaker::Breaker;
+use crate::breaker::{Breaker, Breaker8, TripLogSnapshot};
 pub fn breaker_new() -> *mut Breaker {
     new_default_raw()
 }
 pub fn breaker_free(ptr: *mut Breaker) {
     drop_raw(ptr)
 }
+pub fn breaker8_new() -> *mut Breaker8 {
+    new_default_raw()
+}
+pub fn breaker8_free(ptr: *mut Breaker8) {
+    drop_raw(ptr)
+}
 
-use crate::mag_sign::mag_sign_process_raw;
+use crate::mag_sign::MagSign;
+pub fn mag_sign_new() -> *mut MagSign {
+    new_default_raw()
+}
+pub fn mag_sign_free(ptr: *mut MagSign) {
+    drop_raw(ptr)
+}
 
-use crate::polyshuffle::PolyShuffle;
+use crate::polyshuffle::{PolyShuffle, PolyShuffleSnapshot, DEFAULT_SEED};
 pub fn polyshuffle_new() -> *mut PolyShuffle {
     new_default_raw()
 }
+pub fn polyshuffle_new_with_entropy(entropy: u64) -> *mut PolyShuffle {
+    Box::into_raw(Box::new(PolyShuffle::with_entropy(entropy)))
+}
+pub fn polyshuffle_default_seed() -> u64 {
+    DEFAULT_SEED
+}
 pub fn polyshuffle_free(ptr: *mut PolyShuffle) {
     drop_raw(ptr)
 }
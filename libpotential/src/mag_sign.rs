@@ -1,27 +1,348 @@
-use crate::module_config::{ModuleConfigInfo, RackInput, RackOutput, StaticModuleConfig};
-use crate::rack::{InputPort, OutputPort, Port};
+use crate::module_config::{
+    ModuleConfigInfo, ParamInfo, RackInput, RackOutput, RackParams, StaticModuleConfig,
+};
+use crate::rack::{
+    InputPort, ModuleParam, OutputPort, Param, Port, DEFAULT_SAMPLE_RATE, PORT_MAX_CHANNELS,
+};
+use crate::util::{gate, OutputPulse};
 
 impl StaticModuleConfig for MagSign {
-    const INPUT_PORTS: &'static [&'static std::ffi::CStr] = &[c"Bipolar", c"Magnitude", c"Sign"];
+    const INPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Bipolar",
+        c"Magnitude",
+        c"Sign",
+        c"Sign threshold CV",
+        c"Sign hysteresis CV",
+    ];
 
-    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[c"Magnitude", c"Sign", c"Bipolar"];
+    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Magnitude",
+        c"Sign",
+        c"Bipolar",
+        c"Inverted sign",
+        c"Positive-going trigger",
+        c"Negative-going trigger",
+    ];
+
+    // These must stay in the same order as MagSignParams, and the labels in
+    // the same order as the matching enums.
+    const PARAMS: &'static [ParamInfo] = &[
+        ParamInfo::switch(
+            c"Sign of zero",
+            &[c"Positive", c"Negative", c"Zero", c"Hold previous sign"],
+        ),
+        ParamInfo::new(c"Sign threshold", -10.0, 10.0, 0.0).with_unit(c" V"),
+        ParamInfo::new(c"Sign hysteresis", 0.0, 5.0, 0.0).with_unit(c" V"),
+        ParamInfo::switch(
+            c"Sign output levels",
+            &[c"±1 V", c"±5 V", c"±10 V", c"Gate (0/10 V)"],
+        ),
+        ParamInfo::switch(
+            c"Rectification",
+            &[
+                c"Full wave",
+                c"Half wave (positive)",
+                c"Half wave (negative)",
+            ],
+        ),
+        // Stored as a power of two, so the centre is an exponent of exactly 1.
+        ParamInfo::new(c"Magnitude curve exponent", -2.0, 2.0, 0.0).with_display_base(2.0),
+        ParamInfo::switch(c"Broadcast monophonic sign or magnitude", &[c"Off", c"On"]),
+    ];
 }
 
-pub struct MagSign {}
+// What the sign output should be when the bipolar input is exactly zero.
+// f32::signum() would give +1.0 or -1.0 depending on the sign of the zero,
+// which is arbitrary as far as the user is concerned.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ZeroPolicy {
+    #[default]
+    Positive,
+    Negative,
+    Zero,
+    // Keep the sign of the last nonzero value on the channel.
+    Hold,
+}
 
-impl Default for MagSign {
-    fn default() -> Self {
-        Self {}
+impl ZeroPolicy {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => ZeroPolicy::Negative,
+            2 => ZeroPolicy::Zero,
+            3 => ZeroPolicy::Hold,
+            _ => ZeroPolicy::Positive,
+        }
+    }
+}
+
+// The voltages used on the sign outputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SignFormat {
+    #[default]
+    Bipolar1V,
+    Bipolar5V,
+    Bipolar10V,
+    // 10 V for positive, 0 V otherwise.
+    Gate,
+}
+
+impl SignFormat {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => SignFormat::Bipolar5V,
+            2 => SignFormat::Bipolar10V,
+            3 => SignFormat::Gate,
+            _ => SignFormat::Bipolar1V,
+        }
+    }
+
+    // The output voltage for a sign of +1.0, -1.0 or 0.0.
+    fn voltage(self, sign: f32) -> f32 {
+        match self {
+            SignFormat::Bipolar1V => sign,
+            SignFormat::Bipolar5V => sign * 5.0,
+            SignFormat::Bipolar10V => sign * 10.0,
+            SignFormat::Gate if sign > 0.0 => gate::HIGH,
+            SignFormat::Gate => gate::LOW,
+        }
+    }
+
+    // Read a sign input back in the same format, as a voltage whose sign bit
+    // gives the sign. A gate only has 0 V for a negative sign, so anything
+    // below halfway to high counts as negative.
+    fn sign_from_voltage(self, voltage: f32) -> f32 {
+        match self {
+            SignFormat::Gate if voltage < gate::HIGH / 2.0 => -1.0,
+            SignFormat::Gate => 1.0,
+            _ => voltage,
+        }
+    }
+
+    // The complement of voltage(), for the inverted sign output.
+    fn inverted_voltage(self, sign: f32) -> f32 {
+        match self {
+            SignFormat::Gate => gate::HIGH - self.voltage(sign),
+            _ => -self.voltage(sign),
+        }
+    }
+}
+
+// Which parts of the bipolar input make it to the magnitude output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rectification {
+    #[default]
+    FullWave,
+    // Only positive voltages, with negative ones giving 0 V.
+    HalfWavePositive,
+    // Only (the magnitude of) negative voltages, with positive ones giving 0 V.
+    HalfWaveNegative,
+}
+
+impl Rectification {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Rectification::HalfWavePositive,
+            2 => Rectification::HalfWaveNegative,
+            _ => Rectification::FullWave,
+        }
+    }
+
+    fn rectify(self, value: f32) -> f32 {
+        match self {
+            Rectification::FullWave => value.abs(),
+            Rectification::HalfWavePositive => value.max(0.0),
+            Rectification::HalfWaveNegative => (-value).max(0.0),
+        }
+    }
+}
+
+// Magnitudes are curved as a fraction of this, so that 0 V and 10 V stay put
+// whatever the exponent is.
+const CURVE_RANGE: f32 = 10.0;
+
+// Bend a magnitude by raising it to an exponent. An exponent of exactly 1.0
+// leaves the magnitude untouched, so that decomposing and recomposing a
+// signal gets exactly the same voltages back.
+fn curve(magnitude: f32, exponent: f32) -> f32 {
+    if exponent == 1.0 {
+        return magnitude;
     }
+    (magnitude.abs() / CURVE_RANGE).powf(exponent) * CURVE_RANGE
+}
+
+// Undo curve() with the same exponent, for recomposition.
+fn inverse_curve(magnitude: f32, exponent: f32) -> f32 {
+    curve(magnitude, exponent.recip())
+}
+
+// Settings for mag_sign_process() that come from params.
+struct Shaping {
+    sign_format: SignFormat,
+    rectification: Rectification,
+    curve_exponent: f32,
+    broadcast: bool,
+}
+
+// How the sign of a bipolar channel is decided. The sign flips when the input
+// goes more than half of the hysteresis width past the threshold, and holds
+// while it's inside that band, so a noisy signal won't chatter.
+struct Comparator {
+    threshold: f32,
+    hysteresis: f32,
+    zero_policy: ZeroPolicy,
+}
+
+pub struct MagSign {
+    // The last positive or negative sign given to each channel of the bipolar
+    // input, for ZeroPolicy::Hold and for hysteresis.
+    last_signs: [f32; PORT_MAX_CHANNELS],
+    // The last non-zero sign output of each channel, zero if it hasn't had
+    // one since it appeared, or None if the channel wasn't there last frame.
+    previous_signs: [Option<f32>; PORT_MAX_CHANNELS],
+    positive_pulses: [OutputPulse; PORT_MAX_CHANNELS],
+    negative_pulses: [OutputPulse; PORT_MAX_CHANNELS],
+    sample_rate: f32,
 }
 
 impl MagSign {
+    pub fn new() -> Self {
+        MagSign {
+            last_signs: [1.0; PORT_MAX_CHANNELS],
+            previous_signs: [None; PORT_MAX_CHANNELS],
+            positive_pulses: Default::default(),
+            negative_pulses: Default::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+
+    fn process(
+        &mut self,
+        inputs: &MagSignInput,
+        outputs: &mut MagSignOutput,
+        params: &MagSignParams,
+    ) {
+        let zero_policy = ZeroPolicy::from_index(params.zero_policy.get_index());
+
+        // Keep track of signs whether or not the sign output is connected, so
+        // a held sign is still right when it gets patched.
+        let bipolar_voltages = inputs.bipolar.as_slice().unwrap_or_default();
+        let mut signs = [0.0; PORT_MAX_CHANNELS];
+        for (channel, b) in bipolar_voltages.iter().enumerate() {
+            let threshold =
+                params.threshold.get_value() + inputs.threshold_cv.get_poly_voltage(channel);
+            let hysteresis =
+                params.hysteresis.get_value() + inputs.hysteresis_cv.get_poly_voltage(channel);
+            let comparator = Comparator {
+                threshold,
+                hysteresis: hysteresis.max(0.0),
+                zero_policy,
+            };
+            signs[channel] = self.sign(channel, *b, &comparator);
+        }
+
+        let shaping = Shaping {
+            sign_format: SignFormat::from_index(params.sign_format.get_index()),
+            rectification: Rectification::from_index(params.rectification.get_index()),
+            // The curve param is in octaves of exponent, so that the middle of
+            // its range is exactly 1.0.
+            curve_exponent: params.curve.get_value().exp2(),
+            broadcast: params.broadcast.get_index() != 0,
+        };
+        mag_sign_process(inputs, outputs, &signs, &shaping);
+
+        self.process_crossings(&signs[..bipolar_voltages.len()], outputs);
+    }
+
+    // Fire the trigger outputs for any channels whose sign went positive or
+    // negative since the last frame.
+    fn process_crossings(&mut self, signs: &[f32], outputs: &mut MagSignOutput) {
+        let n = signs.len();
+        for (channel, previous) in self.previous_signs.iter_mut().enumerate() {
+            let sign = signs.get(channel).copied();
+            match (*previous, sign) {
+                // A zero sign doesn't end a run, so +1, 0, +1 isn't a crossing
+                // but -1, 0, +1 is.
+                (Some(last), Some(sign)) if sign != 0.0 => {
+                    if sign > 0.0 && last <= 0.0 {
+                        self.positive_pulses[channel].trigger();
+                    }
+                    if sign < 0.0 && last >= 0.0 {
+                        self.negative_pulses[channel].trigger();
+                    }
+                    *previous = Some(sign);
+                }
+                (Some(_), Some(_)) => {}
+                _ => *previous = sign,
+            }
+        }
+
+        let sample_time = self.sample_rate.recip();
+        let mut positive_voltages = [0.0; PORT_MAX_CHANNELS];
+        let mut negative_voltages = [0.0; PORT_MAX_CHANNELS];
+        for channel in 0..PORT_MAX_CHANNELS {
+            positive_voltages[channel] = self.positive_pulses[channel].process(sample_time);
+            negative_voltages[channel] = self.negative_pulses[channel].process(sample_time);
+        }
+        outputs
+            .positive_trigger
+            .set_voltages_from_slice(&positive_voltages[..n]);
+        outputs
+            .negative_trigger
+            .set_voltages_from_slice(&negative_voltages[..n]);
+    }
+
+    pub fn process_raw(&mut self, inputs: *const Port, outputs: *mut Port, params: *const Param) {
+        let inputs = MagSignInput::from_raw_ptr(inputs);
+        let mut outputs = MagSignOutput::from_raw_ptr(outputs);
+        let params = MagSignParams::from_raw_ptr(params);
+        self.process(&inputs, &mut outputs, &params)
+    }
+
+    fn sign(&mut self, channel: usize, value: f32, comparator: &Comparator) -> f32 {
+        let offset = value - comparator.threshold;
+        let half_width = comparator.hysteresis / 2.0;
+        if offset > half_width || offset < -half_width {
+            let sign = offset.signum();
+            self.last_signs[channel] = sign;
+            return sign;
+        }
+        if comparator.hysteresis > 0.0 {
+            return self.last_signs[channel];
+        }
+        // With no hysteresis, we only get here when the input is exactly at
+        // the threshold.
+        match comparator.zero_policy {
+            ZeroPolicy::Positive => 1.0,
+            ZeroPolicy::Negative => -1.0,
+            ZeroPolicy::Zero => 0.0,
+            ZeroPolicy::Hold => self.last_signs[channel],
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
     pub fn get_module_config_info(&self) -> *mut ModuleConfigInfo {
         ModuleConfigInfo::from_module_instance(self).into_ptr()
     }
 }
 
-fn mag_sign_process(inputs: &MagSignInput, outputs: &mut MagSignOutput) {
+impl Default for MagSign {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The signs of the bipolar input channels have already been worked out by the
+// caller, according to the zero policy.
+fn mag_sign_process(
+    inputs: &MagSignInput,
+    outputs: &mut MagSignOutput,
+    signs: &[f32],
+    shaping: &Shaping,
+) {
+    let sign_format = shaping.sign_format;
     // Upper half processing: decomposition
     if let Some(bipolar_voltages) = inputs.bipolar.as_slice() {
         let n = bipolar_voltages.len();
@@ -32,7 +353,7 @@ fn mag_sign_process(inputs: &MagSignInput, outputs: &mut MagSignOutput) {
                 .iter_mut()
                 .zip(bipolar_voltages)
                 .for_each(|(m, b)| {
-                    *m = b.abs();
+                    *m = curve(shaping.rectification.rectify(*b), shaping.curve_exponent);
                 });
         }
 
@@ -40,69 +361,86 @@ fn mag_sign_process(inputs: &MagSignInput, outputs: &mut MagSignOutput) {
             assert_eq!(n, sign_voltages.len());
             sign_voltages
                 .iter_mut()
-                .zip(bipolar_voltages)
-                .for_each(|(s, b)| {
-                    *s = b.signum();
-                });
+                .zip(signs)
+                .for_each(|(s, sign)| *s = sign_format.voltage(*sign));
+        }
+
+        if let Some(inverted_voltages) = outputs.inverted_sign.as_slice_mut_from_polyphony_count(n)
+        {
+            assert_eq!(n, inverted_voltages.len());
+            inverted_voltages
+                .iter_mut()
+                .zip(signs)
+                .for_each(|(s, sign)| *s = sign_format.inverted_voltage(*sign));
         }
     } else {
         outputs.magnitude.set_polyphony_count(0);
         outputs.sign.set_polyphony_count(0);
+        outputs.inverted_sign.set_polyphony_count(0);
     }
 
     // Lower half processing: recomposition
     //
-    // We only produce as many output channels as we have input signs. We
-    // can pad the input magnitudes with 0.0, but the signs are impossible to
-    // default to either positive or negative without biasing the output.
+    // Normally, we only produce as many output channels as we have input
+    // signs. We can pad the input magnitudes with 0.0, but the signs are
+    // impossible to default to either positive or negative without biasing
+    // the output.
+    //
+    // With broadcasting on, a monophonic input on either side is used for
+    // every channel of the other, so a single sign can flip a whole
+    // polyphonic magnitude signal, or one magnitude can be given many signs.
     if let Some(sign_voltages) = inputs.sign.as_slice() {
-        let n = sign_voltages.len();
+        let magnitude_voltages = inputs.magnitude.as_slice().unwrap_or_default();
+        let n = match (sign_voltages.len(), magnitude_voltages.len()) {
+            (1, magnitude_count) if shaping.broadcast => magnitude_count.max(1),
+            (sign_count, _) => sign_count,
+        };
+        let broadcast_magnitude = shaping.broadcast && magnitude_voltages.len() == 1;
 
         if let Some(bipolar_outputs) = outputs.bipolar.as_slice_mut_from_polyphony_count(n) {
             assert_eq!(bipolar_outputs.len(), n);
 
-            let zero_padded_magnitudes = inputs
-                .magnitude
-                .as_slice()
-                .unwrap_or_default()
-                .iter()
-                .chain(Some(0.0).iter().cycle())
-                .take(n);
-            let mag_sign_pairs = zero_padded_magnitudes.zip(sign_voltages);
-
-            bipolar_outputs.iter_mut().zip(mag_sign_pairs).for_each(
-                |(bipolar_out, (magnitude_in, sign_in))| {
-                    *bipolar_out = magnitude_in.copysign(*sign_in);
-                },
-            );
+            for (channel, bipolar_out) in bipolar_outputs.iter_mut().enumerate() {
+                let magnitude_in = match magnitude_voltages {
+                    [magnitude] if broadcast_magnitude => *magnitude,
+                    _ => magnitude_voltages.get(channel).copied().unwrap_or(0.0),
+                };
+                let sign_in = match sign_voltages {
+                    [sign] => *sign,
+                    _ => sign_voltages[channel],
+                };
+                let sign_in = shaping.sign_format.sign_from_voltage(sign_in);
+                let magnitude = inverse_curve(magnitude_in, shaping.curve_exponent);
+                *bipolar_out = magnitude.copysign(sign_in);
+            }
         }
     } else {
         outputs.bipolar.set_polyphony_count(0);
     }
 }
 
-pub fn mag_sign_process_raw(inputs: *const Port, outputs: *mut Port) {
-    let inputs = MagSignInput::from_raw_ptr(inputs);
-    let mut outputs = MagSignOutput::from_raw_ptr(outputs);
-    mag_sign_process(&inputs, &mut outputs)
-}
-
 struct MagSignInput<'a> {
     bipolar: InputPort<'a>,
     magnitude: InputPort<'a>,
     sign: InputPort<'a>,
+    threshold_cv: InputPort<'a>,
+    hysteresis_cv: InputPort<'a>,
 }
 impl RackInput for MagSignInput<'_> {
-    const COUNT: usize = 3;
+    const COUNT: usize = 5;
 
     fn from_raw_ptr(ports: *const Port) -> Self {
         let bipolar = InputPort::from_raw_port_index(ports, 0);
         let magnitude = InputPort::from_raw_port_index(ports, 1);
         let sign = InputPort::from_raw_port_index(ports, 2);
+        let threshold_cv = InputPort::from_raw_port_index(ports, 3);
+        let hysteresis_cv = InputPort::from_raw_port_index(ports, 4);
         MagSignInput {
             bipolar,
             magnitude,
             sign,
+            threshold_cv,
+            hysteresis_cv,
         }
     }
 }
@@ -111,18 +449,317 @@ struct MagSignOutput<'a> {
     magnitude: OutputPort<'a>,
     sign: OutputPort<'a>,
     bipolar: OutputPort<'a>,
+    inverted_sign: OutputPort<'a>,
+    positive_trigger: OutputPort<'a>,
+    negative_trigger: OutputPort<'a>,
 }
 impl RackOutput for MagSignOutput<'_> {
-    const COUNT: usize = 3;
+    const COUNT: usize = 6;
 
     fn from_raw_ptr(ports: *mut Port) -> Self {
         let magnitude = OutputPort::from_raw_port_index(ports, 0);
         let sign = OutputPort::from_raw_port_index(ports, 1);
         let bipolar = OutputPort::from_raw_port_index(ports, 2);
+        let inverted_sign = OutputPort::from_raw_port_index(ports, 3);
+        let positive_trigger = OutputPort::from_raw_port_index(ports, 4);
+        let negative_trigger = OutputPort::from_raw_port_index(ports, 5);
         MagSignOutput {
             magnitude,
             sign,
             bipolar,
+            inverted_sign,
+            positive_trigger,
+            negative_trigger,
+        }
+    }
+}
+
+struct MagSignParams<'a> {
+    zero_policy: ModuleParam<'a>,
+    threshold: ModuleParam<'a>,
+    hysteresis: ModuleParam<'a>,
+    sign_format: ModuleParam<'a>,
+    rectification: ModuleParam<'a>,
+    curve: ModuleParam<'a>,
+    broadcast: ModuleParam<'a>,
+}
+impl RackParams for MagSignParams<'_> {
+    const COUNT: usize = 7;
+
+    fn from_raw_ptr(params: *const Param) -> Self {
+        let param = ModuleParam::from_raw_param_index;
+        let zero_policy = param(params, 0);
+        let threshold = param(params, 1);
+        let hysteresis = param(params, 2);
+        let sign_format = param(params, 3);
+        let rectification = param(params, 4);
+        let curve = param(params, 5);
+        let broadcast = param(params, 6);
+        MagSignParams {
+            zero_policy,
+            threshold,
+            hysteresis,
+            sign_format,
+            rectification,
+            curve,
+            broadcast,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type ParamValues = [f32; MagSignParams::COUNT];
+
+    fn zero_policy_params(zero_policy: ZeroPolicy) -> ParamValues {
+        [zero_policy as usize as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+    }
+
+    // Run one frame with a monophonic bipolar input, returning the sign and
+    // inverted sign outputs.
+    fn process_frame(
+        mag_sign: &mut MagSign,
+        bipolar: f32,
+        param_values: &ParamValues,
+    ) -> (f32, f32) {
+        let mut ins: [Port; MagSignInput::COUNT] = core::array::from_fn(|_| Port::disconnected());
+        ins[0] = Port::default();
+        OutputPort::wrap(&mut ins[0]).set_monophonic_voltage(bipolar);
+        let mut outs: [Port; MagSignOutput::COUNT] = Default::default();
+        let params = param_values.map(Param::new);
+        mag_sign.process_raw(ins.as_ptr(), outs.as_mut_ptr(), params.as_ptr());
+        let voltage = |port| InputPort::wrap(port).get_monophonic_voltage().unwrap();
+        (voltage(&outs[1]), voltage(&outs[3]))
+    }
+
+    fn process_sign(mag_sign: &mut MagSign, bipolar: f32, zero_policy: ZeroPolicy) -> f32 {
+        process_frame(mag_sign, bipolar, &zero_policy_params(zero_policy)).0
+    }
+
+    #[test]
+    fn test_zero_policy() {
+        for zero in [0.0, -0.0] {
+            let mut m = MagSign::new();
+            assert_eq!(process_sign(&mut m, zero, ZeroPolicy::Positive), 1.0);
+            assert_eq!(process_sign(&mut m, zero, ZeroPolicy::Negative), -1.0);
+            assert_eq!(process_sign(&mut m, zero, ZeroPolicy::Zero), 0.0);
+        }
+
+        // Holding starts out positive, then follows the input.
+        let mut m = MagSign::new();
+        assert_eq!(process_sign(&mut m, 0.0, ZeroPolicy::Hold), 1.0);
+        assert_eq!(process_sign(&mut m, -3.0, ZeroPolicy::Hold), -1.0);
+        assert_eq!(process_sign(&mut m, 0.0, ZeroPolicy::Hold), -1.0);
+        assert_eq!(process_sign(&mut m, 2.0, ZeroPolicy::Hold), 1.0);
+        assert_eq!(process_sign(&mut m, -0.0, ZeroPolicy::Hold), 1.0);
+
+        // Nonzero values are unaffected by the policy.
+        assert_eq!(process_sign(&mut m, -0.5, ZeroPolicy::Positive), -1.0);
+        assert_eq!(process_sign(&mut m, 0.5, ZeroPolicy::Negative), 1.0);
+    }
+
+    #[test]
+    fn test_comparator() {
+        let mut m = MagSign::new();
+        let mut param_values = zero_policy_params(ZeroPolicy::Positive);
+        // A 1 V threshold with 0.5 V of hysteresis.
+        param_values[1] = 1.0;
+        param_values[2] = 0.5;
+        let mut sign = |bipolar| process_frame(&mut m, bipolar, &param_values).0;
+        assert_eq!(sign(0.5), -1.0);
+        assert_eq!(sign(1.2), -1.0);
+        assert_eq!(sign(1.3), 1.0);
+        assert_eq!(sign(0.8), 1.0);
+        assert_eq!(sign(1.0), 1.0);
+        assert_eq!(sign(0.7), -1.0);
+        assert_eq!(sign(1.0), -1.0);
+    }
+
+    #[test]
+    fn test_sign_formats() {
+        let mut m = MagSign::new();
+        let mut param_values = zero_policy_params(ZeroPolicy::Zero);
+        let formats = [
+            (
+                SignFormat::Bipolar1V,
+                [(1.0, -1.0), (-1.0, 1.0), (0.0, -0.0)],
+            ),
+            (
+                SignFormat::Bipolar5V,
+                [(5.0, -5.0), (-5.0, 5.0), (0.0, -0.0)],
+            ),
+            (
+                SignFormat::Bipolar10V,
+                [(10.0, -10.0), (-10.0, 10.0), (0.0, -0.0)],
+            ),
+            (SignFormat::Gate, [(10.0, 0.0), (0.0, 10.0), (0.0, 10.0)]),
+        ];
+        for (format, expected) in formats {
+            param_values[3] = format as usize as f32;
+            for (bipolar, expected) in [2.0, -2.0, 0.0].into_iter().zip(expected) {
+                assert_eq!(process_frame(&mut m, bipolar, &param_values), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gate_round_trip() {
+        let mut m = MagSign::new();
+        let mut param_values = zero_policy_params(ZeroPolicy::Positive);
+        param_values[3] = SignFormat::Gate as usize as f32;
+        let bipolar = [-10.0, -2.5, 0.0, 2.5, 10.0];
+        let (_, recomposed) = round_trip(&mut m, &bipolar, &param_values);
+        assert_eq!(recomposed, bipolar);
+
+        // Gates from elsewhere switch at half way.
+        let mut ins: [Port; MagSignInput::COUNT] = core::array::from_fn(|_| Port::disconnected());
+        let mut outs: [Port; MagSignOutput::COUNT] = Default::default();
+        let params = param_values.map(Param::new);
+        ins[1] = Port::default();
+        ins[2] = Port::default();
+        OutputPort::wrap(&mut ins[1]).set_voltages_from_slice(&[3.0, 3.0, 3.0]);
+        OutputPort::wrap(&mut ins[2]).set_voltages_from_slice(&[4.9, 5.0, -1.0]);
+        m.process_raw(ins.as_ptr(), outs.as_mut_ptr(), params.as_ptr());
+        let recomposed = InputPort::wrap(&outs[2]).as_slice().unwrap().to_vec();
+        assert_eq!(recomposed, [-3.0, 3.0, -3.0]);
+    }
+
+    // Decompose a polyphonic signal and recompose it from the magnitude and
+    // sign outputs, returning the magnitudes and the recomposed signal.
+    fn round_trip(
+        mag_sign: &mut MagSign,
+        bipolar: &[f32],
+        param_values: &ParamValues,
+    ) -> (Vec<f32>, Vec<f32>) {
+        let mut ins: [Port; MagSignInput::COUNT] = core::array::from_fn(|_| Port::disconnected());
+        let mut outs: [Port; MagSignOutput::COUNT] = Default::default();
+        let params = param_values.map(Param::new);
+        ins[0] = Port::default();
+        OutputPort::wrap(&mut ins[0]).set_voltages_from_slice(bipolar);
+        mag_sign.process_raw(ins.as_ptr(), outs.as_mut_ptr(), params.as_ptr());
+        let magnitudes = InputPort::wrap(&outs[0]).as_slice().unwrap().to_vec();
+
+        // Patch the magnitude and sign outputs back into the lower half.
+        let signs = InputPort::wrap(&outs[1]).as_slice().unwrap().to_vec();
+        ins[1] = Port::default();
+        ins[2] = Port::default();
+        OutputPort::wrap(&mut ins[1]).set_voltages_from_slice(&magnitudes);
+        OutputPort::wrap(&mut ins[2]).set_voltages_from_slice(&signs);
+        mag_sign.process_raw(ins.as_ptr(), outs.as_mut_ptr(), params.as_ptr());
+        let recomposed = InputPort::wrap(&outs[2]).as_slice().unwrap().to_vec();
+        (magnitudes, recomposed)
+    }
+
+    #[test]
+    fn test_rectification_and_curve() {
+        let mut m = MagSign::new();
+        let bipolar = [-10.0, -2.5, -0.1, 0.0, 0.3, 1.7, 2.5, 7.123, 10.0];
+        let mut param_values = zero_policy_params(ZeroPolicy::Positive);
+
+        // With no curve, recomposing gives back exactly the same voltages.
+        let (magnitudes, recomposed) = round_trip(&mut m, &bipolar, &param_values);
+        assert_eq!(magnitudes, bipolar.map(f32::abs));
+        assert_eq!(recomposed, bipolar);
+
+        param_values[4] = Rectification::HalfWavePositive as usize as f32;
+        let (magnitudes, _) = round_trip(&mut m, &bipolar, &param_values);
+        assert_eq!(magnitudes, bipolar.map(|b| b.max(0.0)));
+        param_values[4] = Rectification::HalfWaveNegative as usize as f32;
+        let (magnitudes, _) = round_trip(&mut m, &bipolar, &param_values);
+        assert_eq!(magnitudes, bipolar.map(|b| (-b).max(0.0)));
+
+        // A squared curve keeps 0 V and 10 V where they were, and the inverse
+        // curve gets close to the original signal back.
+        param_values[4] = Rectification::FullWave as usize as f32;
+        param_values[5] = 1.0;
+        let (magnitudes, recomposed) = round_trip(&mut m, &bipolar, &param_values);
+        assert_eq!(magnitudes[0], 10.0);
+        assert_eq!(magnitudes[1], 0.625);
+        assert_eq!(magnitudes[3], 0.0);
+        for (r, b) in recomposed.iter().zip(bipolar) {
+            assert!((r - b).abs() < 1e-5, "{r} != {b}");
+        }
+    }
+
+    // Run one frame of recomposition only, returning the bipolar output.
+    fn recompose(magnitude: &[f32], sign: &[f32], broadcast: bool) -> Vec<f32> {
+        let mut m = MagSign::new();
+        let mut ins: [Port; MagSignInput::COUNT] = core::array::from_fn(|_| Port::disconnected());
+        let mut outs: [Port; MagSignOutput::COUNT] = Default::default();
+        let mut param_values = zero_policy_params(ZeroPolicy::Positive);
+        param_values[6] = broadcast as u8 as f32;
+        let params = param_values.map(Param::new);
+        for (port, voltages) in ins[1..3].iter_mut().zip([magnitude, sign]) {
+            *port = Port::default();
+            OutputPort::wrap(port).set_voltages_from_slice(voltages);
+        }
+        m.process_raw(ins.as_ptr(), outs.as_mut_ptr(), params.as_ptr());
+        InputPort::wrap(&outs[2]).as_slice().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let magnitudes = [1.0, 2.0, 3.0];
+        // Without broadcasting, the signs decide the channel count, and
+        // missing magnitudes are zero.
+        assert_eq!(recompose(&magnitudes, &[-1.0], false), [-1.0]);
+        assert_eq!(recompose(&[4.0], &[1.0, -1.0], false), [4.0, -0.0]);
+
+        assert_eq!(recompose(&magnitudes, &[-1.0], true), [-1.0, -2.0, -3.0]);
+        assert_eq!(recompose(&[4.0], &[1.0, -1.0], true), [4.0, -4.0]);
+        // Polyphonic on both sides works as it always did.
+        assert_eq!(recompose(&magnitudes, &[1.0, -1.0], true), [1.0, -2.0]);
+    }
+
+    #[test]
+    fn test_crossing_triggers() {
+        let mut m = MagSign::new();
+        let param_values = zero_policy_params(ZeroPolicy::Zero);
+        let mut ins: [Port; MagSignInput::COUNT] = core::array::from_fn(|_| Port::disconnected());
+        let mut outs: [Port; MagSignOutput::COUNT] = Default::default();
+        let params = param_values.map(Param::new);
+        ins[0] = Port::default();
+        // Run a frame, returning the trigger outputs for each channel.
+        let mut run = |bipolar: &[f32]| {
+            OutputPort::wrap(&mut ins[0]).set_voltages_from_slice(bipolar);
+            m.process_raw(ins.as_ptr(), outs.as_mut_ptr(), params.as_ptr());
+            let triggers = |port| InputPort::wrap(port).as_slice().unwrap().to_vec();
+            (triggers(&outs[4]), triggers(&outs[5]))
+        };
+        let high = gate::HIGH;
+
+        // Nothing fires on the first frame.
+        assert_eq!(run(&[1.0, -1.0]), (vec![0.0, 0.0], vec![0.0, 0.0]));
+        assert_eq!(run(&[-1.0, 1.0]), (vec![0.0, high], vec![high, 0.0]));
+        // The triggers last for 1 ms.
+        for _ in 0..44 {
+            assert_eq!(run(&[-1.0, 1.0]), (vec![0.0, high], vec![high, 0.0]));
         }
+        assert_eq!(run(&[-1.0, 1.0]), (vec![0.0, 0.0], vec![0.0, 0.0]));
+
+        // Passing through zero still counts as a crossing.
+        run(&[0.0, 1.0]);
+        assert_eq!(run(&[1.0, 1.0]).0, vec![high, 0.0]);
+        for _ in 0..45 {
+            run(&[1.0, 1.0]);
+        }
+        // But dropping to zero and coming back to the same sign doesn't.
+        assert_eq!(run(&[0.0, 0.0]), (vec![0.0, 0.0], vec![0.0, 0.0]));
+        assert_eq!(run(&[1.0, 1.0]), (vec![0.0, 0.0], vec![0.0, 0.0]));
+        assert_eq!(run(&[0.0, 0.0]), (vec![0.0, 0.0], vec![0.0, 0.0]));
+        assert_eq!(run(&[-1.0, -1.0]), (vec![0.0, 0.0], vec![high, high]));
+    }
+
+    #[test]
+    fn test_module_config() {
+        let m = MagSign::new();
+        let info = unsafe { Box::from_raw(m.get_module_config_info()) };
+        assert_eq!(info.get_input_port_count(), MagSignInput::COUNT);
+        assert_eq!(info.get_output_port_count(), MagSignOutput::COUNT);
+        assert_eq!(info.get_param_count(), MagSignParams::COUNT);
+        assert_eq!(info.get_param_max(0), 3.0);
+        assert_eq!(info.get_param_label_count(1), 0);
     }
 }
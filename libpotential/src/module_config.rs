@@ -1,6 +1,6 @@
 use std::ffi::{c_char, CStr};
 
-use crate::rack::Port;
+use crate::rack::{Param, Port};
 
 // This trait allows a module to describe its Rack IO configuration (inputs,
 // outputs, etc.) at runtime. The return types are intended to be compatible-ish
@@ -10,6 +10,64 @@ pub trait ModuleConfig {
     fn get_input_port_name(&self, index: usize) -> &'static CStr;
     fn get_output_port_count(&self) -> usize;
     fn get_output_port_name(&self, index: usize) -> &'static CStr;
+    fn get_param_count(&self) -> usize;
+    fn get_param_info(&self, index: usize) -> &'static ParamInfo;
+}
+
+// Describes one of a module's params. A param with labels is configured as a
+// switch with one position per label, counting up from 0. A button is a
+// momentary 0 or 1. Anything else is a continuous param, like a knob.
+pub struct ParamInfo {
+    pub name: &'static CStr,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub unit: &'static CStr,
+    // The same as displayBase in Rack's configParam(), where 0.0 is linear.
+    pub display_base: f32,
+    pub labels: &'static [&'static CStr],
+    pub button: bool,
+}
+
+impl ParamInfo {
+    pub const fn new(name: &'static CStr, min: f32, max: f32, default: f32) -> Self {
+        ParamInfo {
+            name,
+            min,
+            max,
+            default,
+            unit: c"",
+            display_base: 0.0,
+            labels: &[],
+            button: false,
+        }
+    }
+
+    pub const fn button(name: &'static CStr) -> Self {
+        ParamInfo {
+            button: true,
+            ..ParamInfo::new(name, 0.0, 1.0, 0.0)
+        }
+    }
+
+    pub const fn switch(name: &'static CStr, labels: &'static [&'static CStr]) -> Self {
+        ParamInfo {
+            max: (labels.len() - 1) as f32,
+            labels,
+            ..ParamInfo::new(name, 0.0, 0.0, 0.0)
+        }
+    }
+
+    pub const fn with_unit(self, unit: &'static CStr) -> Self {
+        ParamInfo { unit, ..self }
+    }
+
+    pub const fn with_display_base(self, display_base: f32) -> Self {
+        ParamInfo {
+            display_base,
+            ..self
+        }
+    }
 }
 
 // Modules will probably want to implement this instead of manually implementing
@@ -17,6 +75,7 @@ pub trait ModuleConfig {
 pub trait StaticModuleConfig {
     const INPUT_PORTS: &'static [&'static CStr] = &[];
     const OUTPUT_PORTS: &'static [&'static CStr] = &[];
+    const PARAMS: &'static [ParamInfo] = &[];
 }
 
 impl<T> ModuleConfig for T
@@ -40,6 +99,15 @@ where
         assert!(index < Self::OUTPUT_PORTS.len());
         Self::OUTPUT_PORTS[index]
     }
+
+    fn get_param_count(&self) -> usize {
+        Self::PARAMS.len()
+    }
+
+    fn get_param_info(&self, index: usize) -> &'static ParamInfo {
+        assert!(index < Self::PARAMS.len());
+        &Self::PARAMS[index]
+    }
 }
 
 // This type carries all of the same information we could get through a module's
@@ -48,6 +116,7 @@ where
 pub struct ModuleConfigInfo {
     input_port_names: Vec<&'static CStr>,
     output_port_names: Vec<&'static CStr>,
+    params: Vec<&'static ParamInfo>,
 }
 
 impl ModuleConfigInfo {
@@ -62,9 +131,13 @@ impl ModuleConfigInfo {
         let output_port_names = (0..output_port_count)
             .map(|index| module.get_output_port_name(index))
             .collect();
+        let params = (0..module.get_param_count())
+            .map(|index| module.get_param_info(index))
+            .collect();
         Self {
             input_port_names,
             output_port_names,
+            params,
         }
     }
 
@@ -88,6 +161,46 @@ impl ModuleConfigInfo {
     pub fn get_output_port_name(&self, index: usize) -> *const c_char {
         self.output_port_names[index].as_ptr()
     }
+
+    pub fn get_param_count(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn get_param_name(&self, index: usize) -> *const c_char {
+        self.params[index].name.as_ptr()
+    }
+
+    pub fn get_param_min(&self, index: usize) -> f32 {
+        self.params[index].min
+    }
+
+    pub fn get_param_max(&self, index: usize) -> f32 {
+        self.params[index].max
+    }
+
+    pub fn get_param_default(&self, index: usize) -> f32 {
+        self.params[index].default
+    }
+
+    pub fn get_param_unit(&self, index: usize) -> *const c_char {
+        self.params[index].unit.as_ptr()
+    }
+
+    pub fn get_param_display_base(&self, index: usize) -> f32 {
+        self.params[index].display_base
+    }
+
+    pub fn get_param_is_button(&self, index: usize) -> bool {
+        self.params[index].button
+    }
+
+    pub fn get_param_label_count(&self, index: usize) -> usize {
+        self.params[index].labels.len()
+    }
+
+    pub fn get_param_label(&self, index: usize, label: usize) -> *const c_char {
+        self.params[index].labels[label].as_ptr()
+    }
 }
 
 pub trait RackInput: Sized {
@@ -100,6 +213,11 @@ pub trait RackOutput: Sized {
     fn from_raw_ptr(ports: *mut Port) -> Self;
 }
 
+pub trait RackParams: Sized {
+    const COUNT: usize;
+    fn from_raw_ptr(params: *const Param) -> Self;
+}
+
 // A default type for a module with no input ports.
 pub struct NoInputs {}
 impl RackInput for NoInputs {
@@ -120,6 +238,16 @@ impl RackOutput for NoOutputs {
     }
 }
 
+// A default type for a module with no params.
+pub struct NoParams {}
+impl RackParams for NoParams {
+    const COUNT: usize = 0;
+
+    fn from_raw_ptr(_params: *const Param) -> Self {
+        NoParams {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,7 +268,7 @@ mod tests {
 
     #[test]
     fn test_raw_roundtrip() {
-        let mut p = vec![Port::default()];
+        let mut p = [Port::default()];
         let test_voltages = [10.0f32];
         {
             let mut p = OutputPort::wrap(&mut p[0]);
@@ -154,7 +282,6 @@ mod tests {
     fn receiver(inputs: *mut Port) -> Vec<f32> {
         let inputs = TestInput::from_raw_ptr(inputs);
         let voltages = inputs.one.as_slice().unwrap();
-        let voltages = Vec::from(voltages);
-        voltages
+        Vec::from(voltages)
     }
 }
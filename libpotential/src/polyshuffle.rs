@@ -1,24 +1,196 @@
-use crate::module_config::{ModuleConfigInfo, RackInput, RackOutput, StaticModuleConfig};
-use crate::rack::{InputPort, OutputPort, Port, PORT_MAX_CHANNELS};
+use crate::module_config::{
+    ModuleConfigInfo, ParamInfo, RackInput, RackOutput, RackParams, StaticModuleConfig,
+};
+use crate::rack::{InputPort, ModuleParam, OutputPort, Param, Port, PORT_MAX_CHANNELS};
 use crate::util::InputTrigger;
 
 use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
 use rand::SeedableRng;
 
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
 impl StaticModuleConfig for PolyShuffle {
-    const INPUT_PORTS: &'static [&'static std::ffi::CStr] = &[c"Polyphonic", c"Shuffle trigger"];
+    const INPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Polyphonic",
+        c"Shuffle trigger",
+        c"Reset trigger",
+        c"Polyphonic to unshuffle",
+        c"Permutation CV",
+        c"Sort key",
+    ];
+
+    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[
+        c"Shuffled polyphonic",
+        c"Unshuffled polyphonic",
+        c"Permutation CV",
+    ];
+
+    // These must stay in the same order as PolyShuffleParams.
+    const PARAMS: &'static [ParamInfo] = &[
+        ParamInfo::button(c"Reset to original order"),
+        ParamInfo::switch(
+            c"Permutation CV scaling",
+            &[c"1 V per channel", c"Spread over 0-10 V"],
+        ),
+        ParamInfo::switch(
+            c"Permutation CV",
+            &[
+                c"Destination of each input channel",
+                c"Source of each output channel",
+            ],
+        ),
+        ParamInfo::switch(
+            c"Order on trigger",
+            &[
+                c"Shuffle",
+                c"Rotate by one",
+                c"Reverse",
+                c"Interleave",
+                c"Shuffle, moving every channel",
+                c"Sort ascending",
+                c"Sort descending",
+            ],
+        ),
+        ParamInfo::switch(c"Sort", &[c"On trigger", c"Continuously"]),
+    ];
+}
+
+// The seed used until someone picks another one. This is just 8 bytes
+// sampled from /dev/urandom.
+pub const DEFAULT_SEED: u64 = 0xeafcf19c4c7cd3ac;
+
+// What the shuffle trigger does to the order of the channels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OrderMode {
+    // A uniformly random permutation of all 16 channels.
+    #[default]
+    Shuffle,
+    // Move every channel up by one, with the last wrapping around to the
+    // first.
+    Rotate,
+    Reverse,
+    // A perfect riffle shuffle: the first half of the channels go to the even
+    // places and the second half to the odd ones.
+    Interleave,
+    // A random permutation where no channel stays where it was.
+    Derangement,
+    // Order the channels by their voltage, or by the voltage on the sort key
+    // input if it's patched.
+    SortAscending,
+    SortDescending,
+}
+
+impl OrderMode {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => OrderMode::Rotate,
+            2 => OrderMode::Reverse,
+            3 => OrderMode::Interleave,
+            4 => OrderMode::Derangement,
+            5 => OrderMode::SortAscending,
+            6 => OrderMode::SortDescending,
+            _ => OrderMode::Shuffle,
+        }
+    }
+}
+
+// Counts the instances created with with_entropy(), so that two created with
+// the same entropy still get different seeds.
+static INSTANCE_COUNT: AtomicU64 = AtomicU64::new(0);
 
-    const OUTPUT_PORTS: &'static [&'static std::ffi::CStr] = &[c"Shuffled polyphonic"];
+// The SplitMix64 finalizer, for mixing bits together into a seed.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// The parts of a PolyShuffle's state that are saved with a patch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SavedState {
+    seed: u64,
+    position: u64,
+    destinations: [usize; PORT_MAX_CHANNELS],
+}
+
+// What the UI and patch saving see of a PolyShuffle from their own threads.
+// The engine thread publishes its saved state here whenever it changes, and
+// picks up seeds set from the menu on its next frame.
+pub struct PolyShuffleSnapshot {
+    state: Mutex<SavedState>,
+    requested_seed: AtomicU64,
+    seed_requested: AtomicBool,
+}
+
+impl PolyShuffleSnapshot {
+    fn new(state: SavedState) -> Self {
+        PolyShuffleSnapshot {
+            state: Mutex::new(state),
+            requested_seed: AtomicU64::new(0),
+            seed_requested: AtomicBool::new(false),
+        }
+    }
+
+    // Copy the engine's state into the snapshot, unless another thread is
+    // reading it right now. Returns whether it was published.
+    fn publish(&self, state: &SavedState) -> bool {
+        match self.state.try_lock() {
+            Ok(mut snapshot) => {
+                *snapshot = *state;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn take_seed_request(&self) -> Option<u64> {
+        self.seed_requested
+            .swap(false, Ordering::Acquire)
+            .then(|| self.requested_seed.load(Ordering::Relaxed))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SavedState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.lock().seed
+    }
+
+    pub fn get_position(&self) -> u64 {
+        self.lock().position
+    }
+
+    pub fn get_destination(&self, channel: usize) -> usize {
+        self.lock().destinations[channel]
+    }
+
+    // Ask the engine to start again from a new seed, with the channels in
+    // their original order.
+    pub fn request_seed(&self, seed: u64) {
+        self.requested_seed.store(seed, Ordering::Relaxed);
+        self.seed_requested.store(true, Ordering::Release);
+    }
 }
 
 pub struct PolyShuffle {
-    rng: SmallRng,
+    // Each shuffle gets its own RNG, seeded from the seed and the number of
+    // shuffles done since it was set. That makes the whole shuffle history
+    // reproducible from just these two numbers and the current permutation.
+    seed: u64,
+    position: u64,
     shuffle_trigger: InputTrigger,
+    reset_trigger: InputTrigger,
+    reset_button: InputTrigger,
     // This is always 0..16 in some permutation. The current polyphony count
     // is probably smaller than this, so this is only used as a base for the
     // channel_destinations map.
     channel_destinations_full: [usize; PORT_MAX_CHANNELS],
+    // While the permutation CV input is patched, the permutation it asks for
+    // is used in place of channel_destinations_full.
+    external_destinations: Option<[usize; PORT_MAX_CHANNELS]>,
     // Together, channel_count and channel_destinations are a cached subset
     // of channel_destinations_full. If channel_count is Some(n), then n is
     // the polyphony count, and channel_destinations[..n] will contain (0..n)
@@ -31,34 +203,145 @@ pub struct PolyShuffle {
     // the polyphony count changes.
     channel_count: Option<usize>,
     channel_destinations: [usize; PORT_MAX_CHANNELS],
+    snapshot: Arc<PolyShuffleSnapshot>,
+    // The state as it was last published to the snapshot.
+    published_state: SavedState,
+    // The id of the module the restored state was saved from, until the
+    // module is added to the engine.
+    saved_module_id: Option<i64>,
 }
 
 impl PolyShuffle {
+    // A PolyShuffle with its own seed, made from the host's entropy and a
+    // count of instances. new() always uses DEFAULT_SEED instead, which is
+    // handy for tests.
+    pub fn with_entropy(entropy: u64) -> Self {
+        let instance = INSTANCE_COUNT.fetch_add(1, Ordering::Relaxed);
+        let mut p = PolyShuffle::new();
+        p.set_seed(mix(entropy ^ mix(instance)));
+        p.publish_state();
+        p
+    }
+
     pub fn new() -> Self {
-        // This seed is just 8 bytes sampled from /dev/urandom.
-        let rng = SmallRng::seed_from_u64(0xeafcf19c4c7cd3ac);
         let channel_destinations_full: [usize; PORT_MAX_CHANNELS] = core::array::from_fn(|n| n);
         let channel_destinations = channel_destinations_full;
+        let published_state = SavedState {
+            seed: DEFAULT_SEED,
+            position: 0,
+            destinations: channel_destinations_full,
+        };
         PolyShuffle {
-            rng,
+            seed: DEFAULT_SEED,
+            position: 0,
             shuffle_trigger: InputTrigger::new(),
+            reset_trigger: InputTrigger::new(),
+            reset_button: InputTrigger::new(),
             channel_destinations_full,
+            external_destinations: None,
             channel_destinations,
             channel_count: None,
+            snapshot: Arc::new(PolyShuffleSnapshot::new(published_state)),
+            published_state,
+            saved_module_id: None,
         }
     }
 
-    fn process(&mut self, inputs: &PolyShuffleInput, outputs: &mut PolyShuffleOutput) {
+    fn process(
+        &mut self,
+        inputs: &PolyShuffleInput,
+        outputs: &mut PolyShuffleOutput,
+        params: &PolyShuffleParams,
+    ) {
+        if let Some(seed) = self.snapshot.take_seed_request() {
+            self.set_seed(seed);
+        }
+
         let trigger_voltage = inputs
             .shuffle_trigger
             .get_zero_normaled_monophonic_voltage();
-        if self.shuffle_trigger.process_voltage(trigger_voltage) {
-            self.shuffle();
+        // Everything but a full shuffle works on just the channels we're
+        // using.
+        let n = inputs
+            .poly
+            .get_polyphony_count()
+            .unwrap_or(PORT_MAX_CHANNELS);
+        let mode = OrderMode::from_index(params.order_mode.get_index());
+        let sort_descending = match mode {
+            OrderMode::SortAscending => Some(false),
+            OrderMode::SortDescending => Some(true),
+            _ => None,
+        };
+        let sort_continuously =
+            sort_descending.is_some() && params.sort_continuously.get_index() == 1;
+        if self.shuffle_trigger.process_voltage(trigger_voltage) && !sort_continuously {
+            match mode {
+                OrderMode::Shuffle => self.shuffle(),
+                OrderMode::Rotate => self.rearrange(n, |d| *d = (*d + 1) % n),
+                OrderMode::Reverse => self.rearrange(n, |d| *d = n - 1 - *d),
+                OrderMode::Interleave => {
+                    let half = n.div_ceil(2);
+                    self.rearrange(n, |d| {
+                        *d = if *d < half {
+                            *d * 2
+                        } else {
+                            (*d - half) * 2 + 1
+                        }
+                    })
+                }
+                OrderMode::Derangement => {
+                    let derangement = derangement(n, &mut self.next_rng());
+                    self.rearrange(n, |d| *d = derangement[*d]);
+                }
+                OrderMode::SortAscending => self.sort(inputs, n, false),
+                OrderMode::SortDescending => self.sort(inputs, n, true),
+            }
         }
 
+        // A reset wins over a shuffle on the same frame.
+        let reset_voltage = inputs.reset_trigger.get_zero_normaled_monophonic_voltage();
+        let reset_triggered = self.reset_trigger.process_voltage(reset_voltage);
+        let reset_pressed = self
+            .reset_button
+            .process_voltage(params.reset_button.get_value());
+        if reset_triggered || reset_pressed {
+            self.reset_order();
+        }
+
+        // Sorting continuously comes after the reset, which would otherwise
+        // undo it for a frame.
+        if let (true, Some(descending)) = (sort_continuously, sort_descending) {
+            self.sort(inputs, n, descending);
+        }
+
+        // The permutation CV input overrides the shuffled order while it's
+        // patched.
+        let external = match inputs.poly.get_polyphony_count() {
+            Some(n) if inputs.permutation_cv.is_connected() => Some(external_destinations(
+                &inputs.permutation_cv,
+                n,
+                volts_per_index(params, n),
+            )),
+            _ => None,
+        };
+        if external != self.external_destinations {
+            self.external_destinations = external;
+            self.invalidate_channel_destinations();
+        }
+
+        // Work out the permutation once per frame, for the polyphonic input's
+        // channel count. The unshuffle section uses the same one, so it only
+        // falls back to its own channel count when there's nothing to shuffle.
+        let channel_count = inputs
+            .poly
+            .get_polyphony_count()
+            .or_else(|| inputs.unshuffle.get_polyphony_count())
+            .unwrap_or(0);
+        let mut destinations = [0; PORT_MAX_CHANNELS];
+        destinations[..channel_count].copy_from_slice(self.get_channel_destinations(channel_count));
+        let destinations = &destinations[..channel_count];
+
         if let Some(input_voltages) = inputs.poly.as_slice() {
-            let channel_count = input_voltages.len();
-            let destinations = self.get_channel_destinations(channel_count);
             let mut output_buffer = [0.0; PORT_MAX_CHANNELS];
             // Copy inputs to output buffer according to their mapped destinations.
             input_voltages.iter().enumerate().for_each(|(i, value)| {
@@ -71,22 +354,193 @@ impl PolyShuffle {
         } else {
             outputs.shuffled_poly.set_polyphony_count(0);
         }
+
+        process_permutation_cv(inputs, outputs, params, destinations);
+
+        // The unshuffle section applies the inverse permutation, so that
+        // signals that were shuffled above come back out in their original
+        // order. Its output has the polyphonic input's channel count, and any
+        // channels the unshuffle input doesn't have read as they would from
+        // getPolyVoltage().
+        if inputs.unshuffle.is_connected() {
+            let mut output_buffer = [0.0; PORT_MAX_CHANNELS];
+            // Fetch each output from where its channel was sent to.
+            output_buffer[..channel_count]
+                .iter_mut()
+                .zip(destinations)
+                .for_each(|(o, destination_index)| {
+                    *o = inputs.unshuffle.get_poly_voltage(*destination_index);
+                });
+            outputs
+                .unshuffled_poly
+                .set_voltages_from_slice(&output_buffer[..channel_count]);
+        } else {
+            outputs.unshuffled_poly.set_polyphony_count(0);
+        }
+
+        self.publish_state();
     }
 
-    pub fn process_raw(&mut self, inputs: *const Port, outputs: *mut Port) {
+    pub fn process_raw(&mut self, inputs: *const Port, outputs: *mut Port, params: *const Param) {
         let inputs = PolyShuffleInput::from_raw_ptr(inputs);
         let mut outputs = PolyShuffleOutput::from_raw_ptr(outputs);
-        self.process(&inputs, &mut outputs)
+        let params = PolyShuffleParams::from_raw_ptr(params);
+        self.process(&inputs, &mut outputs, &params)
     }
 
     // Shuffle the channel_destinations_full array.
     fn shuffle(&mut self) {
+        let mut rng = self.next_rng();
         self.channel_destinations_full
             .as_mut_slice()
-            .shuffle(&mut self.rng);
+            .shuffle(&mut rng);
         self.invalidate_channel_destinations();
     }
 
+    // Move each of the first `channel_count` channels to a new destination.
+    // `move_channel` is given each of their current destinations to change,
+    // and must map 0..channel_count onto itself.
+    fn rearrange(&mut self, channel_count: usize, move_channel: impl Fn(&mut usize)) {
+        let n = channel_count;
+        let mut destinations = [0; PORT_MAX_CHANNELS];
+        let (used, rest) = destinations.split_at_mut(n);
+        let full = &self.channel_destinations_full;
+        used.iter_mut()
+            .zip(full.iter().filter(|d| **d < n))
+            .for_each(|(o, d)| {
+                *o = *d;
+                move_channel(o);
+            });
+        // The rest of the channels go after them, in the order they were in,
+        // so that the first n come out as rearranged.
+        rest.iter_mut()
+            .zip(full.iter().filter(|d| **d >= n))
+            .for_each(|(o, d)| *o = *d);
+        self.channel_destinations_full = destinations;
+        self.invalidate_channel_destinations();
+    }
+
+    // Send the first `channel_count` channels to places in order of their sort
+    // keys. Channels with the same key keep their original order, and NaNs go
+    // last either way.
+    fn sort(&mut self, inputs: &PolyShuffleInput, channel_count: usize, descending: bool) {
+        let n = channel_count;
+        let keys: [f32; PORT_MAX_CHANNELS] = core::array::from_fn(|i| {
+            if inputs.sort_key.is_connected() {
+                inputs.sort_key.get_poly_voltage(i)
+            } else {
+                inputs.poly.get_poly_voltage(i)
+            }
+        });
+        let mut order: [usize; PORT_MAX_CHANNELS] = core::array::from_fn(|i| i);
+        order[..n].sort_by(|a, b| {
+            let (a, b) = (keys[*a], keys[*b]);
+            a.is_nan().cmp(&b.is_nan()).then_with(|| {
+                if descending {
+                    b.total_cmp(&a)
+                } else {
+                    a.total_cmp(&b)
+                }
+            })
+        });
+        let mut destinations: [usize; PORT_MAX_CHANNELS] = core::array::from_fn(|i| i);
+        order[..n]
+            .iter()
+            .enumerate()
+            .for_each(|(place, channel)| destinations[*channel] = place);
+        // Continuous sorting lands here every frame, so only drop the cached
+        // destinations when the order changes.
+        if destinations != self.channel_destinations_full {
+            self.channel_destinations_full = destinations;
+            self.invalidate_channel_destinations();
+        }
+    }
+
+    // Put the channels back in their original order. This doesn't rewind the
+    // seed, so later shuffles carry on where they left off.
+    fn reset_order(&mut self) {
+        self.channel_destinations_full = core::array::from_fn(|n| n);
+        self.invalidate_channel_destinations();
+    }
+
+    // Get the RNG for the next shuffle, and move on to the one after.
+    fn next_rng(&mut self) -> SmallRng {
+        // SmallRng::seed_from_u64() scrambles its seed well, so nearby
+        // positions don't give similar streams.
+        let rng =
+            SmallRng::seed_from_u64(self.seed ^ self.position.wrapping_mul(0x9e3779b97f4a7c15));
+        self.position = self.position.wrapping_add(1);
+        rng
+    }
+
+    // Start again from a new seed, with the channels in their original order.
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.position = 0;
+        self.reset_order();
+    }
+
+    // Copy the saved state to the snapshot if it has changed, or leave it for
+    // a later frame if the snapshot is busy.
+    fn publish_state(&mut self) {
+        let state = SavedState {
+            seed: self.seed,
+            position: self.position,
+            destinations: self.channel_destinations_full,
+        };
+        if state != self.published_state && self.snapshot.publish(&state) {
+            self.published_state = state;
+        }
+    }
+
+    // The state as seen from other threads. The pointer lives as long as the
+    // PolyShuffle does.
+    pub fn get_snapshot(&self) -> *const PolyShuffleSnapshot {
+        Arc::as_ptr(&self.snapshot)
+    }
+
+    // Restore the state saved with a patch, by the module with the given id
+    // (or -1 if it isn't known). If the destinations aren't a permutation of
+    // all the channels, the original order is used instead. This mustn't be
+    // called while the engine might be processing the module.
+    pub fn restore_state(
+        &mut self,
+        seed: u64,
+        position: u64,
+        destinations: &[usize],
+        module_id: i64,
+    ) {
+        self.saved_module_id = (module_id >= 0).then_some(module_id);
+        self.set_seed(seed);
+        self.position = position;
+        let mut seen = [false; PORT_MAX_CHANNELS];
+        let is_permutation = destinations.len() == PORT_MAX_CHANNELS
+            && destinations
+                .iter()
+                .all(|d| *d < PORT_MAX_CHANNELS && !std::mem::replace(&mut seen[*d], true));
+        if is_permutation {
+            self.channel_destinations_full.copy_from_slice(destinations);
+        }
+        self.publish_state();
+    }
+
+    // Called once the module has been added to the engine with its id. Rack
+    // duplicates a module by saving it and restoring the copy, which then gets
+    // a new id, so if the id doesn't match the saved one this is a copy. A copy
+    // keeps the order it was copied with, but gets a new seed so that it
+    // doesn't shuffle in step with the original.
+    pub fn on_add(&mut self, module_id: i64, entropy: u64) {
+        if self
+            .saved_module_id
+            .take()
+            .is_some_and(|id| id != module_id)
+        {
+            self.seed = mix(self.seed ^ entropy ^ mix(module_id as u64));
+            self.position = 0;
+            self.publish_state();
+        }
+    }
+
     // Mark the channel_destinations cache as invalid, forcing it to be
     // regenerated next time it's needed.
     fn invalidate_channel_destinations(&mut self) {
@@ -101,13 +555,11 @@ impl PolyShuffle {
             Some(c) if c == channel_count => &self.channel_destinations[..channel_count],
             _ => {
                 let n = channel_count;
+                let full = self
+                    .external_destinations
+                    .unwrap_or(self.channel_destinations_full);
                 // Select just the output destinations that fit inside our channel count.
-                let subset = self
-                    .channel_destinations_full
-                    .as_slice()
-                    .iter()
-                    .copied()
-                    .filter(|v| *v < n);
+                let subset = full.iter().copied().filter(|v| *v < n);
                 let channel_destinations = &mut self.channel_destinations[..n];
                 channel_destinations
                     .iter_mut()
@@ -138,32 +590,181 @@ impl Default for PolyShuffle {
     }
 }
 
+// A random permutation of 0..n with no fixed points, if there is one. About
+// 1 in e random permutations has none, so this doesn't take many tries.
+fn derangement(n: usize, rng: &mut SmallRng) -> [usize; PORT_MAX_CHANNELS] {
+    let mut permutation: [usize; PORT_MAX_CHANNELS] = core::array::from_fn(|i| i);
+    if n < 2 {
+        return permutation;
+    }
+    loop {
+        permutation[..n].shuffle(rng);
+        if permutation[..n].iter().enumerate().all(|(i, p)| i != *p) {
+            return permutation;
+        }
+    }
+}
+
+// Show where each channel went, as one voltage per channel of the
+// polyphonic input.
+fn process_permutation_cv(
+    inputs: &PolyShuffleInput,
+    outputs: &mut PolyShuffleOutput,
+    params: &PolyShuffleParams,
+    destinations: &[usize],
+) {
+    let Some(channel_count) = inputs.poly.get_polyphony_count() else {
+        outputs.permutation_cv.set_polyphony_count(0);
+        return;
+    };
+    let mut indices = [0; PORT_MAX_CHANNELS];
+    if params.permutation_sources.get_index() != 0 {
+        for (source, destination) in destinations.iter().enumerate() {
+            indices[*destination] = source;
+        }
+    } else {
+        indices[..channel_count].copy_from_slice(destinations);
+    }
+
+    let volts_per_index = volts_per_index(params, channel_count);
+    let mut voltages = [0.0; PORT_MAX_CHANNELS];
+    for (voltage, index) in voltages.iter_mut().zip(indices).take(channel_count) {
+        *voltage = index as f32 * volts_per_index;
+    }
+    outputs
+        .permutation_cv
+        .set_voltages_from_slice(&voltages[..channel_count]);
+}
+
+// The voltage step between channel indices on the permutation CV ports.
+fn volts_per_index(params: &PolyShuffleParams, channel_count: usize) -> f32 {
+    match params.permutation_spread.get_index() {
+        0 => 1.0,
+        _ if channel_count > 1 => 10.0 / (channel_count - 1) as f32,
+        _ => 0.0,
+    }
+}
+
+// Turn permutation CV into a full permutation of all the channels. Each of
+// the first `channel_count` channels is sent to the index its CV is closest
+// to, or the next free one after it if that's taken. The rest of the channels
+// keep their order after those.
+fn external_destinations(
+    cv: &InputPort,
+    channel_count: usize,
+    volts_per_index: f32,
+) -> [usize; PORT_MAX_CHANNELS] {
+    let mut destinations = [0; PORT_MAX_CHANNELS];
+    let mut taken = [false; PORT_MAX_CHANNELS];
+    for (channel, destination) in destinations.iter_mut().enumerate().take(channel_count) {
+        let index = if volts_per_index > 0.0 {
+            // NaN becomes 0 here.
+            (cv.get_poly_voltage(channel) / volts_per_index)
+                .round()
+                .max(0.0) as usize
+        } else {
+            0
+        };
+        let wanted = index.min(channel_count - 1);
+        let free = (0..channel_count)
+            .map(|offset| (wanted + offset) % channel_count)
+            .find(|index| !taken[*index])
+            .unwrap();
+        taken[free] = true;
+        *destination = free;
+    }
+    for (destination, index) in destinations[channel_count..]
+        .iter_mut()
+        .zip(channel_count..)
+    {
+        *destination = index;
+    }
+    destinations
+}
+
 struct PolyShuffleInput<'a> {
     poly: InputPort<'a>,
     shuffle_trigger: InputPort<'a>,
+    reset_trigger: InputPort<'a>,
+    unshuffle: InputPort<'a>,
+    permutation_cv: InputPort<'a>,
+    sort_key: InputPort<'a>,
 }
 impl RackInput for PolyShuffleInput<'_> {
-    const COUNT: usize = 2;
+    const COUNT: usize = 6;
 
     fn from_raw_ptr(ports: *const Port) -> Self {
         let poly = InputPort::from_raw_port_index(ports, 0);
         let shuffle_trigger = InputPort::from_raw_port_index(ports, 1);
+        let reset_trigger = InputPort::from_raw_port_index(ports, 2);
+        let unshuffle = InputPort::from_raw_port_index(ports, 3);
+        let permutation_cv = InputPort::from_raw_port_index(ports, 4);
+        let sort_key = InputPort::from_raw_port_index(ports, 5);
         PolyShuffleInput {
             poly,
             shuffle_trigger,
+            reset_trigger,
+            unshuffle,
+            permutation_cv,
+            sort_key,
         }
     }
 }
 
 struct PolyShuffleOutput<'a> {
     shuffled_poly: OutputPort<'a>,
+    unshuffled_poly: OutputPort<'a>,
+    permutation_cv: OutputPort<'a>,
 }
 impl RackOutput for PolyShuffleOutput<'_> {
-    const COUNT: usize = 1;
+    const COUNT: usize = 3;
 
     fn from_raw_ptr(ports: *mut Port) -> Self {
         let shuffled_poly = OutputPort::from_raw_port_index(ports, 0);
-        PolyShuffleOutput { shuffled_poly }
+        let unshuffled_poly = OutputPort::from_raw_port_index(ports, 1);
+        let permutation_cv = OutputPort::from_raw_port_index(ports, 2);
+        PolyShuffleOutput {
+            shuffled_poly,
+            unshuffled_poly,
+            permutation_cv,
+        }
+    }
+}
+
+struct PolyShuffleParams<'a> {
+    reset_button: ModuleParam<'a>,
+    permutation_spread: ModuleParam<'a>,
+    permutation_sources: ModuleParam<'a>,
+    order_mode: ModuleParam<'a>,
+    sort_continuously: ModuleParam<'a>,
+}
+
+// The index of each param, which must match ParamId on the C++ side.
+impl PolyShuffleParams<'_> {
+    const RESET_BUTTON: usize = 0;
+    const PERMUTATION_SPREAD: usize = 1;
+    const PERMUTATION_SOURCES: usize = 2;
+    const ORDER_MODE: usize = 3;
+    const SORT_CONTINUOUSLY: usize = 4;
+}
+
+impl RackParams for PolyShuffleParams<'_> {
+    const COUNT: usize = 5;
+
+    fn from_raw_ptr(params: *const Param) -> Self {
+        let param = ModuleParam::from_raw_param_index;
+        let reset_button = param(params, Self::RESET_BUTTON);
+        let permutation_spread = param(params, Self::PERMUTATION_SPREAD);
+        let permutation_sources = param(params, Self::PERMUTATION_SOURCES);
+        let order_mode = param(params, Self::ORDER_MODE);
+        let sort_continuously = param(params, Self::SORT_CONTINUOUSLY);
+        PolyShuffleParams {
+            reset_button,
+            permutation_spread,
+            permutation_sources,
+            order_mode,
+            sort_continuously,
+        }
     }
 }
 
@@ -172,13 +773,105 @@ mod tests {
     use super::*;
     use crate::util::gate;
 
+    // A PolyShuffle with one of each of its ports and params, named after the
+    // fields of PolyShuffleInput, PolyShuffleOutput and PolyShuffleParams.
+    // The inputs start out unpatched and the params at their defaults, so each
+    // test only needs to set up the ones it uses.
+    struct Harness {
+        p: PolyShuffle,
+        poly: Port,
+        shuffle_trigger: Port,
+        reset_trigger: Port,
+        unshuffle: Port,
+        permutation_cv_in: Port,
+        sort_key: Port,
+        shuffled_poly: Port,
+        unshuffled_poly: Port,
+        permutation_cv_out: Port,
+        reset_button: Param,
+        permutation_spread: Param,
+        permutation_sources: Param,
+        order_mode: Param,
+        sort_continuously: Param,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let default = |index: usize| Param::new(PolyShuffle::PARAMS[index].default);
+            Harness {
+                p: PolyShuffle::new(),
+                poly: Port::disconnected(),
+                shuffle_trigger: Port::disconnected(),
+                reset_trigger: Port::disconnected(),
+                unshuffle: Port::disconnected(),
+                permutation_cv_in: Port::disconnected(),
+                sort_key: Port::disconnected(),
+                shuffled_poly: Port::default(),
+                unshuffled_poly: Port::default(),
+                permutation_cv_out: Port::default(),
+                reset_button: default(PolyShuffleParams::RESET_BUTTON),
+                permutation_spread: default(PolyShuffleParams::PERMUTATION_SPREAD),
+                permutation_sources: default(PolyShuffleParams::PERMUTATION_SOURCES),
+                order_mode: default(PolyShuffleParams::ORDER_MODE),
+                sort_continuously: default(PolyShuffleParams::SORT_CONTINUOUSLY),
+            }
+        }
+
+        fn process(&mut self) {
+            let inputs = PolyShuffleInput {
+                poly: InputPort::wrap(&self.poly),
+                shuffle_trigger: InputPort::wrap(&self.shuffle_trigger),
+                reset_trigger: InputPort::wrap(&self.reset_trigger),
+                unshuffle: InputPort::wrap(&self.unshuffle),
+                permutation_cv: InputPort::wrap(&self.permutation_cv_in),
+                sort_key: InputPort::wrap(&self.sort_key),
+            };
+            let mut outputs = PolyShuffleOutput {
+                shuffled_poly: OutputPort::wrap(&mut self.shuffled_poly),
+                unshuffled_poly: OutputPort::wrap(&mut self.unshuffled_poly),
+                permutation_cv: OutputPort::wrap(&mut self.permutation_cv_out),
+            };
+            let params = PolyShuffleParams {
+                reset_button: ModuleParam::wrap(&self.reset_button),
+                permutation_spread: ModuleParam::wrap(&self.permutation_spread),
+                permutation_sources: ModuleParam::wrap(&self.permutation_sources),
+                order_mode: ModuleParam::wrap(&self.order_mode),
+                sort_continuously: ModuleParam::wrap(&self.sort_continuously),
+            };
+            self.p.process(&inputs, &mut outputs, &params);
+        }
+
+        // Send a trigger to the shuffle trigger input, over two frames.
+        fn trigger(&mut self) {
+            for voltage in [gate::HIGH, gate::LOW] {
+                patch(&mut self.shuffle_trigger, &[voltage]);
+                self.process();
+            }
+        }
+    }
+
+    // Patch a cable carrying the given voltages into an input.
+    fn patch(port: &mut Port, voltages: &[f32]) {
+        *port = Port::default();
+        OutputPort::wrap(port).set_voltages_from_slice(voltages);
+    }
+
+    fn voltages_of(port: &Port) -> Vec<f32> {
+        InputPort::wrap(port).as_slice().unwrap().to_vec()
+    }
+
     #[test]
     fn test_shuffle() {
         let mut p = PolyShuffle::new();
         let mut i1 = Port::default();
         let mut o1 = Port::default();
+        let mut o2 = Port::default();
+        let mut o3 = Port::default();
+        let unconnected = Port::disconnected();
         let mut t_low = Port::default();
         let mut t_high = Port::default();
+        let param_values = [0.0; PolyShuffleParams::COUNT].map(Param::new);
+        let params = PolyShuffleParams::from_raw_ptr(param_values.as_ptr());
         let initial_voltages = vec![
             0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 4.5, 5.0, 5.5, 6.0, 6.5, 7.0, 7.5,
         ];
@@ -204,11 +897,17 @@ mod tests {
                 let inputs = PolyShuffleInput {
                     poly: InputPort::wrap(&i1),
                     shuffle_trigger: InputPort::wrap(&t_low),
+                    reset_trigger: InputPort::wrap(&t_low),
+                    unshuffle: InputPort::wrap(&unconnected),
+                    permutation_cv: InputPort::wrap(&unconnected),
+                    sort_key: InputPort::wrap(&unconnected),
                 };
                 let mut outputs = PolyShuffleOutput {
                     shuffled_poly: OutputPort::wrap(&mut o1),
+                    unshuffled_poly: OutputPort::wrap(&mut o2),
+                    permutation_cv: OutputPort::wrap(&mut o3),
                 };
-                p.process(&inputs, &mut outputs);
+                p.process(&inputs, &mut outputs, &params);
             }
             let i1 = InputPort::wrap(&i1);
             let o1 = InputPort::wrap(&o1);
@@ -221,11 +920,17 @@ mod tests {
                 let inputs = PolyShuffleInput {
                     poly: InputPort::wrap(&i1),
                     shuffle_trigger: InputPort::wrap(&t_high),
+                    reset_trigger: InputPort::wrap(&t_low),
+                    unshuffle: InputPort::wrap(&unconnected),
+                    permutation_cv: InputPort::wrap(&unconnected),
+                    sort_key: InputPort::wrap(&unconnected),
                 };
                 let mut outputs = PolyShuffleOutput {
                     shuffled_poly: OutputPort::wrap(&mut o1),
+                    unshuffled_poly: OutputPort::wrap(&mut o2),
+                    permutation_cv: OutputPort::wrap(&mut o3),
                 };
-                p.process(&inputs, &mut outputs);
+                p.process(&inputs, &mut outputs, &params);
             }
 
             {
@@ -261,11 +966,17 @@ mod tests {
                 let inputs = PolyShuffleInput {
                     poly: InputPort::wrap(&i1),
                     shuffle_trigger: InputPort::wrap(&t_low),
+                    reset_trigger: InputPort::wrap(&t_low),
+                    unshuffle: InputPort::wrap(&unconnected),
+                    permutation_cv: InputPort::wrap(&unconnected),
+                    sort_key: InputPort::wrap(&unconnected),
                 };
                 let mut outputs = PolyShuffleOutput {
                     shuffled_poly: OutputPort::wrap(&mut o1),
+                    unshuffled_poly: OutputPort::wrap(&mut o2),
+                    permutation_cv: OutputPort::wrap(&mut o3),
                 };
-                p.process(&inputs, &mut outputs);
+                p.process(&inputs, &mut outputs, &params);
             }
 
             let i1 = InputPort::wrap(&i1);
@@ -283,4 +994,325 @@ mod tests {
             assert_eq!(i1.as_slice().unwrap(), o1.as_slice_mut().unwrap());
         }
     }
+
+    #[test]
+    fn test_seed_and_restore() {
+        let shuffled = |p: &mut PolyShuffle| {
+            p.shuffle();
+            p.channel_destinations_full
+        };
+
+        // The same seed gives the same shuffles.
+        let mut a = PolyShuffle::new();
+        let mut b = PolyShuffle::new();
+        a.set_seed(1234);
+        b.set_seed(1234);
+        for _ in 0..4 {
+            assert_eq!(shuffled(&mut a), shuffled(&mut b));
+        }
+        b.set_seed(5678);
+        assert_ne!(shuffled(&mut a), shuffled(&mut b));
+
+        // Restoring the saved state carries on from where it was.
+        a.publish_state();
+        let saved = &a.snapshot;
+        let destinations: Vec<usize> = (0..PORT_MAX_CHANNELS)
+            .map(|channel| saved.get_destination(channel))
+            .collect();
+        let mut c = PolyShuffle::new();
+        c.restore_state(saved.get_seed(), saved.get_position(), &destinations, 7);
+        c.on_add(7, 42);
+        assert_eq!(c.channel_destinations_full, a.channel_destinations_full);
+        for _ in 0..4 {
+            assert_eq!(shuffled(&mut a), shuffled(&mut c));
+        }
+
+        // A broken permutation falls back to the original order.
+        c.restore_state(1, 2, &[0; PORT_MAX_CHANNELS], -1);
+        assert_eq!(c.channel_destinations_full, core::array::from_fn(|n| n));
+        assert_eq!(c.position, 2);
+        assert_eq!(c.snapshot.get_position(), 2);
+    }
+
+    #[test]
+    fn test_instance_seeds() {
+        assert_eq!(PolyShuffle::new().snapshot.get_seed(), DEFAULT_SEED);
+        // The same entropy doesn't give the same seed twice.
+        let a = PolyShuffle::with_entropy(42);
+        let b = PolyShuffle::with_entropy(42);
+        assert_ne!(a.snapshot.get_seed(), b.snapshot.get_seed());
+    }
+
+    #[test]
+    fn test_duplicated_instances() {
+        let mut original = PolyShuffle::new();
+        original.shuffle();
+        let destinations = original.channel_destinations_full;
+
+        // Loading a patch gives the module back its own id, but a duplicate
+        // restored from the same state gets a new one.
+        let restore = |module_id: i64| {
+            let mut p = PolyShuffle::new();
+            p.restore_state(original.seed, original.position, &destinations, 7);
+            p.on_add(module_id, 42);
+            p
+        };
+        let mut loaded = restore(7);
+        let mut copy = restore(8);
+        let mut another_copy = restore(9);
+        assert_eq!(loaded.seed, original.seed);
+        assert_eq!(loaded.position, original.position);
+        assert_ne!(copy.seed, original.seed);
+        assert_ne!(copy.seed, another_copy.seed);
+        assert_eq!(copy.snapshot.get_seed(), copy.seed);
+
+        // Copies start from the same order, then go their own way.
+        assert_eq!(copy.channel_destinations_full, destinations);
+        original.shuffle();
+        loaded.shuffle();
+        copy.shuffle();
+        another_copy.shuffle();
+        assert_eq!(
+            loaded.channel_destinations_full,
+            original.channel_destinations_full
+        );
+        assert_ne!(
+            copy.channel_destinations_full,
+            original.channel_destinations_full
+        );
+        assert_ne!(
+            copy.channel_destinations_full,
+            another_copy.channel_destinations_full
+        );
+
+        // A module that wasn't restored keeps its seed.
+        let mut p = PolyShuffle::new();
+        p.on_add(3, 42);
+        assert_eq!(p.seed, DEFAULT_SEED);
+    }
+
+    #[test]
+    fn test_seed_request() {
+        let mut h = Harness::new();
+        h.p.shuffle();
+
+        // A seed set from the menu only takes effect on the engine's next
+        // frame.
+        h.p.snapshot.request_seed(1234);
+        assert_eq!(h.p.seed, DEFAULT_SEED);
+        h.process();
+        assert_eq!(h.p.seed, 1234);
+        assert_eq!(h.p.snapshot.get_seed(), 1234);
+        assert_eq!(h.p.snapshot.get_position(), 0);
+        assert_eq!(h.p.snapshot.get_destination(3), 3);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut h = Harness::new();
+        let identity: [usize; PORT_MAX_CHANNELS] = core::array::from_fn(|n| n);
+        h.reset_button = Param::new(1.0);
+
+        h.p.shuffle();
+        let position = h.p.position;
+        assert_ne!(h.p.channel_destinations_full, identity);
+        h.process();
+        assert_eq!(h.p.channel_destinations_full, identity);
+        assert_eq!(h.p.position, position);
+        // Holding the button down doesn't keep resetting.
+        h.p.shuffle();
+        h.process();
+        assert_ne!(h.p.channel_destinations_full, identity);
+    }
+
+    #[test]
+    fn test_unshuffle() {
+        let mut h = Harness::new();
+        h.p.shuffle();
+        for n in [1, 5, 16] {
+            let voltages: Vec<f32> = (0..n).map(|channel| channel as f32).collect();
+            patch(&mut h.poly, &voltages);
+            h.process();
+
+            // Patch the shuffled output into the unshuffle input.
+            let shuffled = voltages_of(&h.shuffled_poly);
+            patch(&mut h.unshuffle, &shuffled);
+            h.process();
+            assert_eq!(voltages_of(&h.unshuffled_poly), voltages);
+            if n > 1 {
+                assert_ne!(shuffled, voltages);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unshuffle_channel_count() {
+        let mut h = Harness::new();
+        h.p.shuffle();
+        let voltages: Vec<f32> = (0..5).map(|channel| channel as f32 + 1.0).collect();
+        patch(&mut h.poly, &voltages);
+        h.process();
+        let destinations = h.p.get_destinations().to_vec();
+
+        // An unshuffle input with fewer channels uses the same permutation,
+        // and the channels it doesn't have read as zero.
+        let shuffled = voltages_of(&h.shuffled_poly);
+        patch(&mut h.unshuffle, &shuffled[..3]);
+        h.process();
+        assert_eq!(h.p.get_destinations(), destinations);
+        let expected: Vec<f32> = destinations
+            .iter()
+            .map(|d| shuffled[..3].get(*d).copied().unwrap_or(0.0))
+            .collect();
+        assert_eq!(voltages_of(&h.unshuffled_poly), expected);
+
+        // Permutation CV for the main input still applies to the unshuffle
+        // input when their channel counts differ.
+        let external: Vec<f32> = [4, 3, 2, 1, 0].map(|d| d as f32).to_vec();
+        patch(&mut h.permutation_cv_in, &external);
+        patch(&mut h.unshuffle, &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        h.process();
+        assert_eq!(h.p.get_destinations(), [4, 3, 2, 1, 0]);
+        assert_eq!(voltages_of(&h.unshuffled_poly), [4.0, 3.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_permutation_cv() {
+        let mut h = Harness::new();
+        h.p.shuffle();
+        // Send each channel's own index through, so the shuffled output
+        // shows the source of each output channel.
+        let voltages: Vec<f32> = (0..5).map(|channel| channel as f32).collect();
+        patch(&mut h.poly, &voltages);
+        let mut run = |spread: f32, sources: f32| {
+            h.permutation_spread = Param::new(spread);
+            h.permutation_sources = Param::new(sources);
+            h.process();
+            (
+                voltages_of(&h.permutation_cv_out),
+                voltages_of(&h.shuffled_poly),
+            )
+        };
+
+        let (sources, shuffled) = run(0.0, 1.0);
+        assert_eq!(sources, shuffled);
+        let (destinations, _) = run(0.0, 0.0);
+        for (channel, destination) in destinations.iter().enumerate() {
+            assert_eq!(shuffled[*destination as usize], channel as f32);
+        }
+        let (spread, _) = run(1.0, 0.0);
+        assert_eq!(
+            spread,
+            destinations.iter().map(|d| d * 2.5).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_external_permutation() {
+        let mut h = Harness::new();
+        patch(&mut h.poly, &[0.0, 1.0, 2.0, 3.0]);
+        let mut run = |cv: &[f32]| {
+            patch(&mut h.permutation_cv_in, cv);
+            h.process();
+            voltages_of(&h.shuffled_poly)
+        };
+
+        assert_eq!(run(&[3.0, 2.0, 1.0, 0.0]), [3.0, 2.0, 1.0, 0.0]);
+        assert_eq!(run(&[1.2, 2.9, 0.1, -4.0]), [2.0, 0.0, 3.0, 1.0]);
+        // Collisions move along to the next free channel.
+        assert_eq!(run(&[2.0, 2.0, 2.0, 2.0]), [2.0, 3.0, 0.0, 1.0]);
+        // NaN counts as 0 V, and so do missing CV channels.
+        assert_eq!(run(&[f32::NAN, 9.0]), [0.0, 2.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn test_order_modes() {
+        let mut h = Harness::new();
+        patch(&mut h.poly, &[0.0, 1.0, 2.0, 3.0, 4.0]);
+        // Send a trigger in the given mode, returning the output.
+        let trigger = |h: &mut Harness, mode: OrderMode| {
+            h.order_mode = Param::new(mode as usize as f32);
+            h.trigger();
+            voltages_of(&h.shuffled_poly)
+        };
+
+        assert_eq!(
+            trigger(&mut h, OrderMode::Rotate),
+            [4.0, 0.0, 1.0, 2.0, 3.0]
+        );
+        assert_eq!(
+            trigger(&mut h, OrderMode::Rotate),
+            [3.0, 4.0, 0.0, 1.0, 2.0]
+        );
+        assert_eq!(
+            trigger(&mut h, OrderMode::Reverse),
+            [2.0, 1.0, 0.0, 4.0, 3.0]
+        );
+
+        h.p = PolyShuffle::new();
+        assert_eq!(
+            trigger(&mut h, OrderMode::Interleave),
+            [0.0, 3.0, 1.0, 4.0, 2.0]
+        );
+
+        h.p = PolyShuffle::new();
+        let mut previous = vec![0.0, 1.0, 2.0, 3.0, 4.0];
+        for _ in 0..20 {
+            let next = trigger(&mut h, OrderMode::Derangement);
+            assert!(next.iter().zip(&previous).all(|(a, b)| a != b));
+            previous = next;
+        }
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut h = Harness::new();
+        patch(&mut h.poly, &[0.5, f32::NAN, -1.0, 0.5, 2.0]);
+        let ascending = OrderMode::SortAscending as usize as f32;
+        let descending = OrderMode::SortDescending as usize as f32;
+
+        // On trigger, the order only changes when the trigger comes in.
+        h.order_mode = Param::new(ascending);
+        h.process();
+        assert_eq!(
+            voltages_of(&h.permutation_cv_out),
+            [0.0, 1.0, 2.0, 3.0, 4.0]
+        );
+        // Equal voltages keep their order, and the NaN goes last.
+        h.trigger();
+        assert_eq!(
+            voltages_of(&h.permutation_cv_out),
+            [1.0, 4.0, 0.0, 2.0, 3.0]
+        );
+        h.order_mode = Param::new(descending);
+        h.process();
+        assert_eq!(
+            voltages_of(&h.permutation_cv_out),
+            [1.0, 4.0, 0.0, 2.0, 3.0]
+        );
+        h.trigger();
+        assert_eq!(
+            voltages_of(&h.permutation_cv_out),
+            [1.0, 4.0, 3.0, 2.0, 0.0]
+        );
+
+        // Continuously, a sort key follows the voltages without a trigger,
+        // even through a reset.
+        h.p = PolyShuffle::new();
+        h.order_mode = Param::new(ascending);
+        h.sort_continuously = Param::new(1.0);
+        h.reset_button = Param::new(1.0);
+        patch(&mut h.sort_key, &[4.0, 3.0, 2.0, 1.0, 0.0]);
+        h.process();
+        assert_eq!(
+            voltages_of(&h.permutation_cv_out),
+            [4.0, 3.0, 2.0, 1.0, 0.0]
+        );
+        patch(&mut h.sort_key, &[0.0, 1.0, 2.0, 3.0, 4.0]);
+        h.process();
+        assert_eq!(
+            voltages_of(&h.permutation_cv_out),
+            [0.0, 1.0, 2.0, 3.0, 4.0]
+        );
+    }
 }
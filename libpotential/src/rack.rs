@@ -26,13 +26,36 @@ impl Default for Port {
     }
 }
 
-// FIXME: fill in methods
+impl Port {
+    // An unconnected port. This is mostly for unit tests, since the engine
+    // owns all of the real ones.
+    pub fn disconnected() -> Self {
+        Port {
+            channels: None,
+            ..Port::default()
+        }
+    }
+}
+
+// Rack's default engine sample rate, used until the host tells us otherwise.
+pub const DEFAULT_SAMPLE_RATE: f32 = 44100.0;
+
+// Our internal representation of rack::engine::Param. As with Port, we only
+// ever borrow these from the Rack engine.
 #[repr(C)]
 #[derive(Default)]
 pub struct Param {
     value: f32,
 }
 
+impl Param {
+    // This is mostly for unit tests, where we don't have an engine to own the
+    // params for us.
+    pub fn new(value: f32) -> Self {
+        Param { value }
+    }
+}
+
 // FIXME: fill in methods
 #[repr(C)]
 #[derive(Default)]
@@ -51,7 +74,11 @@ impl<'a> InputPort<'a> {
         InputPort { inner: port }
     }
 
-    // Construct an InputPort from a raw pointer from across the FFI.
+    // Construct an InputPort from a raw pointer from across the FFI. These
+    // are only made inside a module's process call, where Rack guarantees the
+    // port is valid and nothing else is writing to it, so they're safe to
+    // call from there despite the raw pointer.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn from_raw_port(port: *const Port) -> Self {
         let inner = unsafe { &*port };
         InputPort { inner }
@@ -59,7 +86,9 @@ impl<'a> InputPort<'a> {
 
     // Construct an InputPort from a raw base pointer and an index. If we know
     // there's an array on the other side, this makes it easier to construct a
-    // few of these in a row.
+    // few of these in a row. The pointer must be valid for the index, as with
+    // from_raw_port().
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn from_raw_port_index(port: *const Port, index: usize) -> Self {
         let inner = unsafe {
             let port = port.add(index);
@@ -84,6 +113,21 @@ impl<'a> InputPort<'a> {
         self.get_monophonic_voltage().unwrap_or(0.0)
     }
 
+    pub fn is_connected(&self) -> bool {
+        self.inner.channels.is_some()
+    }
+
+    // Get the voltage for one channel of a polyphonic signal, like Rack's
+    // getPolyVoltage(). A monophonic input applies to every channel, and an
+    // unconnected one reads as 0.0.
+    pub fn get_poly_voltage(&self, channel: usize) -> f32 {
+        match self.as_slice() {
+            Some([voltage]) => *voltage,
+            Some(voltages) => voltages.get(channel).copied().unwrap_or(0.0),
+            None => 0.0,
+        }
+    }
+
     // Get the number of polyphony channels on the port. An unconnected port
     // returns None. The polyphony count is guaranteed to be non-zero if the
     // port is connected.
@@ -113,11 +157,14 @@ impl<'a> OutputPort<'a> {
         OutputPort { inner: port }
     }
 
+    // See InputPort::from_raw_port().
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn from_raw_port(port: *mut Port) -> Self {
         let inner = unsafe { &mut *port };
         OutputPort { inner }
     }
 
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn from_raw_port_index(port: *mut Port, index: usize) -> Self {
         let inner = unsafe {
             let port = port.add(index);
@@ -243,8 +290,85 @@ pub struct ModuleParam<'a> {
     inner: &'a Param,
 }
 
-impl ModuleParam<'_> {
+impl<'a> ModuleParam<'a> {
+    pub fn wrap(param: &'a Param) -> Self {
+        ModuleParam { inner: param }
+    }
+
+    // Construct a ModuleParam from a raw base pointer and an index, in the
+    // same way as InputPort::from_raw_port_index().
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn from_raw_param_index(param: *const Param, index: usize) -> Self {
+        let inner = unsafe {
+            let param = param.add(index);
+            &*param
+        };
+        ModuleParam { inner }
+    }
+
     pub fn get_value(&self) -> f32 {
         self.inner.value
     }
+
+    // Get the value rounded to the nearest integer, for switches and other
+    // params that select between a fixed set of options.
+    pub fn get_index(&self) -> usize {
+        self.inner.value.round().max(0.0) as usize
+    }
+}
+
+// Message buffers for talking to the modules on either side of this one
+// through Rack's expander mechanism. Each side is None unless there's a
+// compatible module there; deciding that is up to the C++ side, which also
+// requests the message flips once we're done.
+//
+// Incoming messages are the ones our neighbours wrote on the previous frame.
+// Outgoing ones will be seen by our neighbours on the next frame.
+pub struct Expanders<'a, T> {
+    pub from_left: Option<&'a T>,
+    pub from_right: Option<&'a T>,
+    pub to_left: Option<&'a mut T>,
+    pub to_right: Option<&'a mut T>,
+}
+
+impl<'a, T> Expanders<'a, T> {
+    // No neighbours on either side.
+    pub fn none() -> Self {
+        Expanders {
+            from_left: None,
+            from_right: None,
+            to_left: None,
+            to_right: None,
+        }
+    }
+
+    // Construct from raw pointers from across the FFI, any of which may be
+    // null. The rest point into the expander message buffers, which Rack
+    // doesn't swap until the process call is over.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn from_raw_ptrs(
+        from_left: *const T,
+        from_right: *const T,
+        to_left: *mut T,
+        to_right: *mut T,
+    ) -> Self {
+        unsafe {
+            Expanders {
+                from_left: from_left.as_ref(),
+                from_right: from_right.as_ref(),
+                to_left: to_left.as_mut(),
+                to_right: to_right.as_mut(),
+            }
+        }
+    }
+
+    // Send a message to whichever neighbours are there to receive it.
+    pub fn send(&mut self, to_left: T, to_right: T) {
+        if let Some(message) = self.to_left.as_deref_mut() {
+            *message = to_left;
+        }
+        if let Some(message) = self.to_right.as_deref_mut() {
+            *message = to_right;
+        }
+    }
 }
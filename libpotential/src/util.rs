@@ -50,3 +50,35 @@ impl InputTrigger {
         self.state = InputTriggerState::Low;
     }
 }
+
+// Generates fixed-length trigger pulses for an output port, like Rack's
+// dsp::PulseGenerator.
+#[derive(Debug, Default)]
+pub struct OutputPulse {
+    // Time left in the current pulse, in seconds.
+    remaining: f32,
+}
+
+impl OutputPulse {
+    // The usual Rack trigger length.
+    pub const TRIGGER_DURATION: f32 = 1e-3;
+
+    pub fn new() -> Self {
+        OutputPulse { remaining: 0.0 }
+    }
+
+    // Start a new pulse, restarting it if one is already in progress.
+    pub fn trigger(&mut self) {
+        self.remaining = Self::TRIGGER_DURATION;
+    }
+
+    // Advance by one sample, returning the output voltage for it.
+    pub fn process(&mut self, sample_time: f32) -> f32 {
+        if self.remaining > 0.0 {
+            self.remaining -= sample_time;
+            gate::HIGH
+        } else {
+            gate::LOW
+        }
+    }
+}
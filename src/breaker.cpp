@@ -1,27 +1,244 @@
-#include "plugin.hpp"
+#include "breaker.hpp"
+
+#include <ctime>
+
+static std::string tripCauseLabel(rustlib::TripCause cause) {
+    switch (cause) {
+    case rustlib::TripCause::Peak:
+        return "peak";
+    case rustlib::TripCause::Rms:
+        return "RMS";
+    case rustlib::TripCause::SustainedPeak:
+        return "sustained peak";
+    case rustlib::TripCause::DcOffset:
+        return "DC offset";
+    case rustlib::TripCause::Growth:
+        return "runaway growth";
+    case rustlib::TripCause::Slew:
+        return "slew rate";
+    case rustlib::TripCause::Manual:
+        return "manual trip";
+    case rustlib::TripCause::Linked:
+        return "linked breaker tripped";
+    default:
+        return "unknown";
+    }
+}
+
+// The names trip causes are saved under, which don't depend on the order of
+// the enum.
+static const std::vector<std::pair<rustlib::TripCause, std::string>>
+    TRIP_CAUSE_NAMES = {
+        {rustlib::TripCause::Peak, "peak"},
+        {rustlib::TripCause::Rms, "rms"},
+        {rustlib::TripCause::SustainedPeak, "sustainedPeak"},
+        {rustlib::TripCause::DcOffset, "dcOffset"},
+        {rustlib::TripCause::Growth, "growth"},
+        {rustlib::TripCause::Manual, "manual"},
+        {rustlib::TripCause::Slew, "slew"},
+        {rustlib::TripCause::Linked, "linked"},
+};
+
+json_t *tripCauseToJson(rustlib::TripCause cause) {
+    for (const auto &name : TRIP_CAUSE_NAMES) {
+        if (name.first == cause) {
+            return json_string(name.second.c_str());
+        }
+    }
+    return json_null();
+}
+
+bool tripCauseFromJson(json_t *causeJ, rustlib::TripCause &cause) {
+    const char *causeName = json_string_value(causeJ);
+    for (const auto &name : TRIP_CAUSE_NAMES) {
+        if (causeName && name.second == causeName) {
+            cause = name.first;
+            return true;
+        }
+    }
+    return false;
+}
+
+std::string formatTripEvent(Module *module, const rustlib::TripEvent &event) {
+    // Trips are logged in wall-clock time, shown here in local time.
+    time_t seconds = static_cast<time_t>(event.time);
+    const struct tm *local = std::localtime(&seconds);
+    char time[32] = "Unknown time";
+    if (local) {
+        std::strftime(time, sizeof(time), "%Y-%m-%d %H:%M:%S", local);
+    }
+    if (event.cause == rustlib::TripCause::Manual ||
+        event.cause == rustlib::TripCause::Linked) {
+        return string::f("%s: %s", time, tripCauseLabel(event.cause).c_str());
+    }
+
+    std::string port = "Input " + std::to_string(event.port + 1);
+    if (event.port < module->inputInfos.size()) {
+        port = module->inputInfos[event.port]->getName();
+    }
+    const char *unit = event.cause == rustlib::TripCause::Slew ? "V/ms" : "V";
+    return string::f("%s: %s channel %d, %s %.2f %s", time, port.c_str(),
+                     static_cast<int>(event.channel + 1),
+                     tripCauseLabel(event.cause).c_str(), event.level, unit);
+}
+
+json_t *tripLogToJson(const rustlib::TripLogSnapshot *log) {
+    json_t *rootJ = json_object();
+    json_object_set_new(rootJ, "tripCount",
+                        json_integer(log->get_trip_count()));
+
+    // Events are saved oldest first, which is the order to restore them in.
+    json_t *eventsJ = json_array();
+    for (size_t i = log->get_trip_log_len(); i-- > 0;) {
+        rustlib::TripEvent event{};
+        if (!log->get_trip_event(i, event)) {
+            continue;
+        }
+        json_t *eventJ = json_object();
+        json_object_set_new(eventJ, "time", json_real(event.time));
+        json_object_set_new(eventJ, "port", json_integer(event.port));
+        json_object_set_new(eventJ, "channel", json_integer(event.channel));
+        json_object_set_new(eventJ, "level", json_real(event.level));
+        json_object_set_new(eventJ, "cause", tripCauseToJson(event.cause));
+        json_array_append_new(eventsJ, eventJ);
+    }
+    json_object_set_new(rootJ, "tripLog", eventsJ);
+    return rootJ;
+}
+
+void appendTripLogMenu(Menu *menu, Module *module,
+                       const rustlib::TripLogSnapshot *log) {
+    menu->addChild(new MenuSeparator);
+    menu->addChild(createMenuLabel(
+        "Trip count: " + std::to_string(log->get_trip_count())));
+    menu->addChild(createSubmenuItem("Recent trips", "", [=](Menu *menu) {
+        size_t len = log->get_trip_log_len();
+        if (len == 0) {
+            menu->addChild(createMenuLabel("None"));
+        }
+        for (size_t i = 0; i < len; ++i) {
+            rustlib::TripEvent event{};
+            if (!log->get_trip_event(i, event)) {
+                break;
+            }
+            menu->addChild(createMenuLabel(formatTripEvent(module, event)));
+        }
+    }));
+    menu->addChild(createMenuItem("Clear trip log", "",
+                                  [=]() { log->request_clear(); }));
+}
+
+bool isBreakerModule(Module *module) {
+    return module->model == modelBreaker || module->model == modelBreaker8;
+}
+
+void configBreakerParams(Module *module) {
+    // The trip mode and protection labels must stay in the same order as
+    // TripMode and Protection on the Rust side.
+    module->configSwitch(
+        TRIP_MODE_PARAM, 0.f, 2.f, 0.f, "Trip mode",
+        {"Peak", "RMS over window", "Peak sustained for window"});
+    module->configParam(TRIP_WINDOW_PARAM, 1.f, 1000.f, 50.f, "Trip window",
+                        " ms");
+    module->configSwitch(DC_DETECT_PARAM, 0.f, 1.f, 0.f, "Trip on DC offset",
+                         {"Off", "On"});
+    module->configParam(DC_LIMIT_PARAM, 0.1f, 10.f, 2.f, "DC offset limit",
+                        " V");
+    module->configSwitch(GROWTH_DETECT_PARAM, 0.f, 1.f, 0.f,
+                         "Trip on runaway level growth", {"Off", "On"});
+    module->configParam(GROWTH_TIME_PARAM, 100.f, 5000.f, 500.f,
+                        "Runaway growth time", " ms");
+    module->configButton(RESET_BUTTON_PARAM, "Reset breaker");
+    module->configButton(TRIP_BUTTON_PARAM, "Trip breaker");
+    module->configSwitch(ARM_PARAM, 0.f, 1.f, 1.f, "Armed",
+                         {"Disarmed (pass through)", "Armed"});
+    module->configSwitch(PROTECTION_PARAM, 0.f, 2.f, 0.f, "When tripped",
+                         {"Mute", "Limit", "Soft clip"});
+    module->configParam(LIMIT_ATTACK_PARAM, 0.1f, 50.f, 1.f, "Limiter attack",
+                        " ms");
+    module->configParam(LIMIT_RELEASE_PARAM, 10.f, 2000.f, 100.f,
+                        "Limiter release", " ms");
+    module->configSwitch(NORMAL_PARAM, 0.f, 1.f, 0.f,
+                         "Normal unpatched inputs to the one before",
+                         {"Off", "On"});
+    module->configSwitch(SLEW_DETECT_PARAM, 0.f, 1.f, 0.f,
+                         "Trip on slew rate", {"Off", "On"});
+    module->configParam(SLEW_LIMIT_PARAM, 10.f, 2000.f, 200.f,
+                        "Slew rate limit", " V/ms");
+    module->configSwitch(LINK_PARAM, 0.f, 1.f, 0.f,
+                         "Link with neighbouring breakers", {"Off", "On"});
+}
+
+void appendBreakerContextMenu(Menu *menu, Module *module) {
+    menu->addChild(new MenuSeparator);
+    menu->addChild(
+        createParamBoolMenuItem("Normal unpatched inputs to the one before",
+                                &module->params[NORMAL_PARAM]));
+    menu->addChild(createParamBoolMenuItem(
+        "Link trips and resets with neighbouring breakers",
+        &module->params[LINK_PARAM]));
+
+    menu->addChild(new MenuSeparator);
+    menu->addChild(
+        createSwitchParamMenuItem("Trip mode", module, TRIP_MODE_PARAM));
+    menu->addChild(
+        new ParamMenuSlider(module->paramQuantities[TRIP_WINDOW_PARAM]));
+
+    menu->addChild(new MenuSeparator);
+    menu->addChild(
+        createSwitchParamMenuItem("When tripped", module, PROTECTION_PARAM));
+    menu->addChild(
+        new ParamMenuSlider(module->paramQuantities[LIMIT_ATTACK_PARAM]));
+    menu->addChild(
+        new ParamMenuSlider(module->paramQuantities[LIMIT_RELEASE_PARAM]));
+
+    menu->addChild(new MenuSeparator);
+    menu->addChild(createParamBoolMenuItem("Trip on DC offset",
+                                           &module->params[DC_DETECT_PARAM]));
+    menu->addChild(
+        new ParamMenuSlider(module->paramQuantities[DC_LIMIT_PARAM]));
+    menu->addChild(createParamBoolMenuItem(
+        "Trip on runaway level growth", &module->params[GROWTH_DETECT_PARAM]));
+    menu->addChild(
+        new ParamMenuSlider(module->paramQuantities[GROWTH_TIME_PARAM]));
+    menu->addChild(createParamBoolMenuItem("Trip on slew rate",
+                                           &module->params[SLEW_DETECT_PARAM]));
+    menu->addChild(
+        new ParamMenuSlider(module->paramQuantities[SLEW_LIMIT_PARAM]));
+}
 
 struct Breaker : Module {
     rustlib::Breaker *inner = NULL;
+    // Owned by inner, and safe to read from the UI thread.
+    const rustlib::TripLogSnapshot *tripLog = NULL;
+    ExpanderMessages<rustlib::BreakerLinkMessage> link;
 
-    enum ParamId { PARAMS_LEN };
+    enum ParamId { PARAMS_LEN = BREAKER_PARAMS_LEN };
     enum InputId { LEFT_INPUT, RIGHT_INPUT, RESET_INPUT, INPUTS_LEN };
     enum OutputId {
         TRIPPED_GATE_OUTPUT,
         LEFT_OUTPUT,
         RIGHT_OUTPUT,
+        TRIP_TRIGGER_OUTPUT,
+        RESET_TRIGGER_OUTPUT,
         OUTPUTS_LEN
     };
     enum LightId { TRIPPED_LIGHT, LIGHTS_LEN };
 
     Breaker() {
         this->inner = rustlib::breaker_new();
+        this->tripLog = this->inner->get_trip_log();
         config(PARAMS_LEN, INPUTS_LEN, OUTPUTS_LEN, LIGHTS_LEN);
+        configBreakerParams(this);
+        link.attach(this);
         configInput(LEFT_INPUT, "Left");
         configInput(RIGHT_INPUT, "Right");
         configInput(RESET_INPUT, "Reset breaker");
         configOutput(TRIPPED_GATE_OUTPUT, "Tripped gate");
         configOutput(LEFT_OUTPUT, "Left");
         configOutput(RIGHT_OUTPUT, "Right");
+        configOutput(TRIP_TRIGGER_OUTPUT, "Trip trigger");
+        configOutput(RESET_TRIGGER_OUTPUT, "Reset trigger");
     }
 
     ~Breaker() { rustlib::breaker_free(this->inner); }
@@ -30,10 +247,25 @@ struct Breaker : Module {
         bool tripped_status = false;
         const rustlib::Port *inputs = ffi_port(&this->inputs[0]);
         rustlib::Port *outputs = ffi_port(&this->outputs[0]);
+        const rustlib::Param *params = ffi_param(&this->params[0]);
 
-        this->inner->process_raw(inputs, outputs, tripped_status);
+        link.prepare(this, isBreakerModule);
+        this->inner->process_raw(inputs, outputs, params, link.fromLeft,
+                                 link.fromRight, link.toLeft, link.toRight,
+                                 tripped_status);
+        link.send(this);
         lights[TRIPPED_LIGHT].setBrightness(static_cast<float>(tripped_status));
     }
+
+    json_t *dataToJson() override { return tripLogToJson(this->tripLog); }
+
+    void dataFromJson(json_t *rootJ) override {
+        tripLogFromJson(this->inner, rootJ);
+    }
+
+    void onSampleRateChange(const SampleRateChangeEvent &e) override {
+        this->inner->set_sample_rate(e.sampleRate);
+    }
 };
 
 struct BreakerWidget : ModuleWidget {
@@ -57,16 +289,33 @@ struct BreakerWidget : ModuleWidget {
         addInput(createInputCentered<PJ301MPort>(mm2px(Vec(12.7, 62.891)),
                                                  module, Breaker::RESET_INPUT));
 
+        addParam(createParamCentered<VCVButton>(mm2px(Vec(7.7, 74.0)), module,
+                                                RESET_BUTTON_PARAM));
+        addParam(createParamCentered<VCVButton>(mm2px(Vec(17.7, 74.0)),
+                                                module, TRIP_BUTTON_PARAM));
+        addParam(createParamCentered<CKSS>(mm2px(Vec(12.7, 100.5)), module,
+                                           ARM_PARAM));
+
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(16.26, 44.0)), module, Breaker::TRIPPED_GATE_OUTPUT));
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(7.304, 113.133)), module, Breaker::LEFT_OUTPUT));
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(17.937, 113.259)), module, Breaker::RIGHT_OUTPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(7.35, 87.5)), module, Breaker::RESET_TRIGGER_OUTPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(18.05, 87.5)), module, Breaker::TRIP_TRIGGER_OUTPUT));
 
         addChild(createLightCentered<MediumLight<RedLight>>(
             mm2px(Vec(7.006, 44.0)), module, Breaker::TRIPPED_LIGHT));
     }
+
+    void appendContextMenu(Menu *menu) override {
+        Breaker *module = getModule<Breaker>();
+        appendBreakerContextMenu(menu, module);
+        appendTripLogMenu(menu, module, module->tripLog);
+    }
 };
 
 Model *modelBreaker = createModel<Breaker, BreakerWidget>("Breaker");
\ No newline at end of file
@@ -0,0 +1,75 @@
+#pragma once
+#include "plugin.hpp"
+
+// Params shared by all of the Breaker variants. These must stay in the same
+// order as BreakerParams on the Rust side.
+enum BreakerParamId {
+    TRIP_MODE_PARAM,
+    TRIP_WINDOW_PARAM,
+    DC_DETECT_PARAM,
+    DC_LIMIT_PARAM,
+    GROWTH_DETECT_PARAM,
+    GROWTH_TIME_PARAM,
+    RESET_BUTTON_PARAM,
+    TRIP_BUTTON_PARAM,
+    ARM_PARAM,
+    PROTECTION_PARAM,
+    LIMIT_ATTACK_PARAM,
+    LIMIT_RELEASE_PARAM,
+    NORMAL_PARAM,
+    SLEW_DETECT_PARAM,
+    SLEW_LIMIT_PARAM,
+    LINK_PARAM,
+    BREAKER_PARAMS_LEN
+};
+
+// Call after Module::config() to set up the shared params.
+void configBreakerParams(Module *module);
+
+void appendBreakerContextMenu(Menu *menu, Module *module);
+
+// Whether a module is one of the Breaker variants, which can be linked
+// together through expander messages.
+bool isBreakerModule(Module *module);
+
+std::string formatTripEvent(Module *module, const rustlib::TripEvent &event);
+
+json_t *tripCauseToJson(rustlib::TripCause cause);
+// Returns false if the cause isn't one we know the name of.
+bool tripCauseFromJson(json_t *causeJ, rustlib::TripCause &cause);
+
+// Trip log persistence and display, shared by the Breaker variants. The
+// engine thread owns the log, so saving and the menu read the snapshot it
+// publishes instead.
+
+json_t *tripLogToJson(const rustlib::TripLogSnapshot *log);
+
+void appendTripLogMenu(Menu *menu, Module *module,
+                       const rustlib::TripLogSnapshot *log);
+
+// Restoring goes through the breaker itself, which is safe because Rack
+// doesn't process a module while it's loading its data. T is the Rust
+// breaker type.
+template <typename T> void tripLogFromJson(T *inner, json_t *rootJ) {
+    inner->clear_trip_log();
+
+    size_t i;
+    json_t *eventJ;
+    json_array_foreach(json_object_get(rootJ, "tripLog"), i, eventJ) {
+        rustlib::TripEvent event{};
+        // Skip events whose cause we don't recognise, rather than guess.
+        if (!tripCauseFromJson(json_object_get(eventJ, "cause"), event.cause)) {
+            continue;
+        }
+        event.time = json_number_value(json_object_get(eventJ, "time"));
+        event.port = json_integer_value(json_object_get(eventJ, "port"));
+        event.channel = json_integer_value(json_object_get(eventJ, "channel"));
+        event.level = json_number_value(json_object_get(eventJ, "level"));
+        inner->restore_trip_event(event);
+    }
+
+    json_t *countJ = json_object_get(rootJ, "tripCount");
+    if (countJ) {
+        inner->set_trip_count(json_integer_value(countJ));
+    }
+}
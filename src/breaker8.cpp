@@ -0,0 +1,118 @@
+#include "breaker.hpp"
+
+static const int SIGNAL_COUNT = 8;
+
+struct Breaker8 : Module {
+    rustlib::Breaker8 *inner = NULL;
+    // Owned by inner, and safe to read from the UI thread.
+    const rustlib::TripLogSnapshot *tripLog = NULL;
+    ExpanderMessages<rustlib::BreakerLinkMessage> link;
+
+    enum ParamId { PARAMS_LEN = BREAKER_PARAMS_LEN };
+    enum InputId {
+        ENUMS(SIGNAL_INPUTS, SIGNAL_COUNT),
+        RESET_INPUT,
+        INPUTS_LEN
+    };
+    enum OutputId {
+        TRIPPED_GATE_OUTPUT,
+        ENUMS(SIGNAL_OUTPUTS, SIGNAL_COUNT),
+        TRIP_TRIGGER_OUTPUT,
+        RESET_TRIGGER_OUTPUT,
+        OUTPUTS_LEN
+    };
+    enum LightId { TRIPPED_LIGHT, LIGHTS_LEN };
+
+    Breaker8() {
+        this->inner = rustlib::breaker8_new();
+        this->tripLog = this->inner->get_trip_log();
+        config(PARAMS_LEN, INPUTS_LEN, OUTPUTS_LEN, LIGHTS_LEN);
+        configBreakerParams(this);
+        link.attach(this);
+        for (int i = 0; i < SIGNAL_COUNT; ++i) {
+            configInput(SIGNAL_INPUTS + i, string::f("Input %d", i + 1));
+            configOutput(SIGNAL_OUTPUTS + i, string::f("Output %d", i + 1));
+        }
+        configInput(RESET_INPUT, "Reset breaker");
+        configOutput(TRIPPED_GATE_OUTPUT, "Tripped gate");
+        configOutput(TRIP_TRIGGER_OUTPUT, "Trip trigger");
+        configOutput(RESET_TRIGGER_OUTPUT, "Reset trigger");
+    }
+
+    ~Breaker8() { rustlib::breaker8_free(this->inner); }
+
+    void process(const ProcessArgs &args) override {
+        bool tripped_status = false;
+        const rustlib::Port *inputs = ffi_port(&this->inputs[0]);
+        rustlib::Port *outputs = ffi_port(&this->outputs[0]);
+        const rustlib::Param *params = ffi_param(&this->params[0]);
+
+        link.prepare(this, isBreakerModule);
+        this->inner->process_raw(inputs, outputs, params, link.fromLeft,
+                                 link.fromRight, link.toLeft, link.toRight,
+                                 tripped_status);
+        link.send(this);
+        lights[TRIPPED_LIGHT].setBrightness(static_cast<float>(tripped_status));
+    }
+
+    json_t *dataToJson() override { return tripLogToJson(this->tripLog); }
+
+    void dataFromJson(json_t *rootJ) override {
+        tripLogFromJson(this->inner, rootJ);
+    }
+
+    void onSampleRateChange(const SampleRateChangeEvent &e) override {
+        this->inner->set_sample_rate(e.sampleRate);
+    }
+};
+
+struct Breaker8Widget : ModuleWidget {
+    Breaker8Widget(Breaker8 *module) {
+        setModule(module);
+        setPanel(
+            createPanel(asset::plugin(pluginInstance, "res/Breaker8.svg")));
+
+        addChild(createWidget<ScrewSilver>(Vec(RACK_GRID_WIDTH, 0)));
+        addChild(createWidget<ScrewSilver>(
+            Vec(box.size.x - 2 * RACK_GRID_WIDTH, 0)));
+        addChild(createWidget<ScrewSilver>(
+            Vec(RACK_GRID_WIDTH, RACK_GRID_HEIGHT - RACK_GRID_WIDTH)));
+        addChild(
+            createWidget<ScrewSilver>(Vec(box.size.x - 2 * RACK_GRID_WIDTH,
+                                          RACK_GRID_HEIGHT - RACK_GRID_WIDTH)));
+
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(12.7, 20.0)), module, Breaker8::RESET_INPUT));
+        addChild(createLightCentered<MediumLight<RedLight>>(
+            mm2px(Vec(29.0, 20.0)), module, Breaker8::TRIPPED_LIGHT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(38.1, 20.0)), module, Breaker8::TRIPPED_GATE_OUTPUT));
+
+        addParam(createParamCentered<VCVButton>(mm2px(Vec(25.4, 36.0)),
+                                                module, RESET_BUTTON_PARAM));
+        addParam(createParamCentered<VCVButton>(mm2px(Vec(25.4, 49.0)),
+                                                module, TRIP_BUTTON_PARAM));
+        addParam(createParamCentered<CKSS>(mm2px(Vec(25.4, 62.5)), module,
+                                           ARM_PARAM));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(25.4, 83.0)), module, Breaker8::TRIP_TRIGGER_OUTPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(25.4, 99.0)), module, Breaker8::RESET_TRIGGER_OUTPUT));
+
+        for (int i = 0; i < SIGNAL_COUNT; ++i) {
+            float y = 34.0 + 11.5 * i;
+            addInput(createInputCentered<PJ301MPort>(
+                mm2px(Vec(12.7, y)), module, Breaker8::SIGNAL_INPUTS + i));
+            addOutput(createOutputCentered<PJ301MPort>(
+                mm2px(Vec(38.1, y)), module, Breaker8::SIGNAL_OUTPUTS + i));
+        }
+    }
+
+    void appendContextMenu(Menu *menu) override {
+        Breaker8 *module = getModule<Breaker8>();
+        appendBreakerContextMenu(menu, module);
+        appendTripLogMenu(menu, module, module->tripLog);
+    }
+};
+
+Model *modelBreaker8 = createModel<Breaker8, Breaker8Widget>("Breaker8");
@@ -1,6 +1,7 @@
 #pragma once
 
 #include <engine/Module.hpp>
+#include <engine/Param.hpp>
 #include <engine/Port.hpp>
 #include <ffi.rs.h>
 
@@ -8,15 +9,96 @@ inline rustlib::Port *ffi_port(rack::engine::Port *p) {
     return reinterpret_cast<rustlib::Port *>(p);
 }
 
+inline rustlib::Param *ffi_param(rack::engine::Param *p) {
+    return reinterpret_cast<rustlib::Param *>(p);
+}
+
+// Double buffered expander messages of type T for talking to the modules on
+// either side, set up the way Rack expects. The buffers live in here, so this
+// needs to live as long as the module does.
+template <typename T> struct ExpanderMessages {
+    T buffers[4]{};
+
+    // Pointers for the Rust side, which are NULL when there's no compatible
+    // module on that side. Set by prepare().
+    const T *fromLeft = NULL;
+    const T *fromRight = NULL;
+    T *toLeft = NULL;
+    T *toRight = NULL;
+
+    // Call from the module's constructor.
+    void attach(rack::engine::Module *module) {
+        module->leftExpander.producerMessage = &buffers[0];
+        module->leftExpander.consumerMessage = &buffers[1];
+        module->rightExpander.producerMessage = &buffers[2];
+        module->rightExpander.consumerMessage = &buffers[3];
+    }
+
+    // Call before processing. isCompatible decides which neighbours we can
+    // exchange messages with; they need to be using the same message type.
+    template <typename F>
+    void prepare(rack::engine::Module *module, F isCompatible) {
+        rack::engine::Module *left = module->leftExpander.module;
+        rack::engine::Module *right = module->rightExpander.module;
+        bool leftOk = left && isCompatible(left);
+        bool rightOk = right && isCompatible(right);
+
+        fromLeft = leftOk
+                       ? static_cast<const T *>(
+                             module->leftExpander.consumerMessage)
+                       : NULL;
+        fromRight = rightOk
+                        ? static_cast<const T *>(
+                              module->rightExpander.consumerMessage)
+                        : NULL;
+        toLeft = leftOk ? static_cast<T *>(left->rightExpander.producerMessage)
+                        : NULL;
+        toRight = rightOk
+                      ? static_cast<T *>(right->leftExpander.producerMessage)
+                      : NULL;
+    }
+
+    // Call after processing, to hand anything we wrote to our neighbours.
+    void send(rack::engine::Module *module) {
+        if (toLeft) {
+            module->leftExpander.module->rightExpander.requestMessageFlip();
+        }
+        if (toRight) {
+            module->rightExpander.module->leftExpander.requestMessageFlip();
+        }
+    }
+};
+
 inline void configure_from_info(rack::engine::Module *rack_module,
                                 rustlib::ModuleConfigInfo *config) {
     size_t in_port_count = config->get_input_port_count();
     size_t out_port_count = config->get_output_port_count();
-    size_t param_count = 0;
+    size_t param_count = config->get_param_count();
     size_t light_count = 0;
     rack_module->config(param_count, in_port_count, out_port_count,
                         light_count);
 
+    for (size_t i = 0; i < param_count; ++i) {
+        auto name = std::string(config->get_param_name(i));
+        float min = config->get_param_min(i);
+        float max = config->get_param_max(i);
+        float default_value = config->get_param_default(i);
+        size_t label_count = config->get_param_label_count(i);
+        if (config->get_param_is_button(i)) {
+            rack_module->configButton(i, name);
+        } else if (label_count > 0) {
+            std::vector<std::string> labels;
+            for (size_t label = 0; label < label_count; ++label) {
+                labels.push_back(std::string(config->get_param_label(i, label)));
+            }
+            rack_module->configSwitch(i, min, max, default_value, name, labels);
+        } else {
+            auto unit = std::string(config->get_param_unit(i));
+            rack_module->configParam(i, min, max, default_value, name, unit,
+                                     config->get_param_display_base(i));
+        }
+    }
+
     for (size_t i = 0; i < in_port_count; ++i) {
         auto name = std::string(config->get_input_port_name(i));
         rack_module->configInput(i, name);
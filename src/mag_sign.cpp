@@ -1,30 +1,60 @@
 #include "plugin.hpp"
 
 struct MagSign : Module {
-    enum ParamId { PARAMS_LEN };
-    enum InputId { BIPOLAR_INPUT, MAGNITUDE_INPUT, SIGN_INPUT, INPUTS_LEN };
+    rustlib::MagSign *inner = NULL;
+    rustlib::ModuleConfigInfo *config_info = NULL;
+
+    // The ports and params are configured by the Rust side. These must stay
+    // in the same order as the ones there.
+    enum ParamId {
+        ZERO_POLICY_PARAM,
+        THRESHOLD_PARAM,
+        HYSTERESIS_PARAM,
+        SIGN_FORMAT_PARAM,
+        RECTIFICATION_PARAM,
+        CURVE_PARAM,
+        BROADCAST_PARAM,
+        PARAMS_LEN
+    };
+    enum InputId {
+        BIPOLAR_INPUT,
+        MAGNITUDE_INPUT,
+        SIGN_INPUT,
+        THRESHOLD_CV_INPUT,
+        HYSTERESIS_CV_INPUT,
+        INPUTS_LEN
+    };
     enum OutputId {
         MAGNITUDE_OUTPUT,
         SIGN_OUTPUT,
         BIPOLAR_OUTPUT,
+        INVERTED_SIGN_OUTPUT,
+        POSITIVE_TRIGGER_OUTPUT,
+        NEGATIVE_TRIGGER_OUTPUT,
         OUTPUTS_LEN
     };
     enum LightId { LIGHTS_LEN };
 
     MagSign() {
-        config(PARAMS_LEN, INPUTS_LEN, OUTPUTS_LEN, LIGHTS_LEN);
-        configInput(BIPOLAR_INPUT, "Bipolar");
-        configInput(MAGNITUDE_INPUT, "Magnitude");
-        configInput(SIGN_INPUT, "Sign");
-        configOutput(MAGNITUDE_OUTPUT, "Magnitude");
-        configOutput(SIGN_OUTPUT, "Sign");
-        configOutput(BIPOLAR_OUTPUT, "Bipolar");
+        this->inner = rustlib::mag_sign_new();
+        this->config_info = this->inner->get_module_config_info();
+        configure_from_info(this, this->config_info);
+    }
+
+    ~MagSign() {
+        rustlib::mag_sign_free(this->inner);
+        rustlib::module_config_free(this->config_info);
     }
 
     void process(const ProcessArgs &args) override {
         const rustlib::Port *inputs = ffi_port(&this->inputs[0]);
         rustlib::Port *outputs = ffi_port(&this->outputs[0]);
-        rustlib::mag_sign_process_raw(inputs, outputs);
+        const rustlib::Param *params = ffi_param(&this->params[0]);
+        this->inner->process_raw(inputs, outputs, params);
+    }
+
+    void onSampleRateChange(const SampleRateChangeEvent &e) override {
+        this->inner->set_sample_rate(e.sampleRate);
     }
 };
 
@@ -49,12 +79,46 @@ struct MagSignWidget : ModuleWidget {
         addInput(createInputCentered<PJ301MPort>(mm2px(Vec(7.62, 97.367)),
                                                  module, MagSign::SIGN_INPUT));
 
+        addParam(createParamCentered<RoundSmallBlackKnob>(
+            mm2px(Vec(22.86, 22.3)), module, MagSign::THRESHOLD_PARAM));
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 31.3)), module, MagSign::THRESHOLD_CV_INPUT));
+        addParam(createParamCentered<RoundSmallBlackKnob>(
+            mm2px(Vec(22.86, 43.8)), module, MagSign::HYSTERESIS_PARAM));
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 52.8)), module, MagSign::HYSTERESIS_CV_INPUT));
+
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(7.62, 37.504)), module, MagSign::MAGNITUDE_OUTPUT));
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(7.62, 53.5)), module, MagSign::SIGN_OUTPUT));
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(7.62, 113.363)), module, MagSign::BIPOLAR_OUTPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 66.3)), module, MagSign::INVERTED_SIGN_OUTPUT));
+
+        addParam(createParamCentered<RoundSmallBlackKnob>(
+            mm2px(Vec(22.86, 81.372)), module, MagSign::CURVE_PARAM));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 97.367)), module,
+            MagSign::POSITIVE_TRIGGER_OUTPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 113.363)), module,
+            MagSign::NEGATIVE_TRIGGER_OUTPUT));
+    }
+
+    void appendContextMenu(Menu *menu) override {
+        MagSign *module = getModule<MagSign>();
+        menu->addChild(new MenuSeparator);
+        menu->addChild(createSwitchParamMenuItem(
+            "Sign of zero", module, MagSign::ZERO_POLICY_PARAM));
+        menu->addChild(createSwitchParamMenuItem(
+            "Sign output levels", module, MagSign::SIGN_FORMAT_PARAM));
+        menu->addChild(createSwitchParamMenuItem(
+            "Rectification", module, MagSign::RECTIFICATION_PARAM));
+        menu->addChild(createParamBoolMenuItem(
+            "Broadcast monophonic sign or magnitude to all channels",
+            &module->params[MagSign::BROADCAST_PARAM]));
     }
 };
 
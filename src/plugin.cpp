@@ -7,6 +7,7 @@ void init(Plugin *p) {
 
     p->addModel(modelMagSign);
     p->addModel(modelBreaker);
+    p->addModel(modelBreaker8);
     p->addModel(modelPolyShuffle);
 
     // Any other plugin initialization may go here.
@@ -11,4 +11,33 @@ extern Plugin *pluginInstance;
 // Declare each Model, defined in each module source file
 extern Model *modelMagSign;
 extern Model *modelBreaker;
+extern Model *modelBreaker8;
 extern Model *modelPolyShuffle;
+
+// A context menu slider for a param that doesn't have a panel control.
+struct ParamMenuSlider : ui::Slider {
+    ParamMenuSlider(Quantity *quantity) {
+        this->quantity = quantity;
+        box.size.x = 200.f;
+    }
+};
+
+// A context menu checkbox for an on/off switch param.
+inline MenuItem *createParamBoolMenuItem(std::string text, Param *param) {
+    return createBoolMenuItem(
+        text, "", [=]() { return param->getValue() != 0.f; },
+        [=](bool enabled) { param->setValue(enabled ? 1.f : 0.f); });
+}
+
+// A context menu submenu for picking the position of a switch param, using
+// the labels it was configured with.
+inline MenuItem *createSwitchParamMenuItem(std::string text, Module *module,
+                                           int paramId) {
+    auto *quantity =
+        dynamic_cast<engine::SwitchQuantity *>(module->paramQuantities[paramId]);
+    Param *param = &module->params[paramId];
+    return createIndexSubmenuItem(
+        text, quantity->labels,
+        [=]() { return static_cast<size_t>(param->getValue()); },
+        [=](size_t index) { param->setValue(static_cast<float>(index)); });
+}
@@ -2,15 +2,42 @@
 
 struct PolyShuffle : Module {
     rustlib::PolyShuffle *inner = NULL;
+    // Owned by inner, and safe to use from the UI thread.
+    const rustlib::PolyShuffleSnapshot *snapshot = NULL;
     rustlib::ModuleConfigInfo *config_info = NULL;
 
-    enum ParamId { PARAMS_LEN };
-    enum InputId { INPUT_INPUT, SHUFFLE_TRIGGER_INPUT, INPUTS_LEN };
-    enum OutputId { OUTPUT_OUTPUT, OUTPUTS_LEN };
+    // The ports and params are configured by the Rust side. These must stay
+    // in the same order as the ones there.
+    enum ParamId {
+        RESET_BUTTON_PARAM,
+        PERMUTATION_SPREAD_PARAM,
+        PERMUTATION_SOURCES_PARAM,
+        ORDER_MODE_PARAM,
+        SORT_CONTINUOUSLY_PARAM,
+        PARAMS_LEN
+    };
+    enum InputId {
+        INPUT_INPUT,
+        SHUFFLE_TRIGGER_INPUT,
+        RESET_TRIGGER_INPUT,
+        UNSHUFFLE_INPUT,
+        PERMUTATION_CV_INPUT,
+        SORT_KEY_INPUT,
+        INPUTS_LEN
+    };
+    enum OutputId {
+        OUTPUT_OUTPUT,
+        UNSHUFFLE_OUTPUT,
+        PERMUTATION_CV_OUTPUT,
+        OUTPUTS_LEN
+    };
     enum LightId { LIGHTS_LEN };
 
     PolyShuffle() {
-        this->inner = rustlib::polyshuffle_new();
+        // Each instance gets its own seed, so that several in one patch don't
+        // all shuffle the same way.
+        this->inner = rustlib::polyshuffle_new_with_entropy(random::u64());
+        this->snapshot = this->inner->get_snapshot();
         this->config_info = this->inner->get_module_config_info();
         configure_from_info(this, this->config_info);
     }
@@ -23,8 +50,89 @@ struct PolyShuffle : Module {
     void process(const ProcessArgs &args) override {
         const rustlib::Port *inputs = ffi_port(&this->inputs[0]);
         rustlib::Port *outputs = ffi_port(&this->outputs[0]);
+        const rustlib::Param *params = ffi_param(&this->params[0]);
 
-        this->inner->process_raw(inputs, outputs);
+        this->inner->process_raw(inputs, outputs, params);
+    }
+
+    // The seed and position are saved as strings, since they don't fit in a
+    // JSON integer.
+    json_t *dataToJson() override {
+        json_t *rootJ = json_object();
+        json_object_set_new(
+            rootJ, "seed",
+            json_string(std::to_string(this->snapshot->get_seed()).c_str()));
+        json_object_set_new(
+            rootJ, "position",
+            json_string(std::to_string(this->snapshot->get_position()).c_str()));
+        json_t *destinationsJ = json_array();
+        for (size_t i = 0; i < PORT_MAX_CHANNELS; ++i) {
+            json_array_append_new(destinationsJ,
+                                  json_integer(this->snapshot->get_destination(i)));
+        }
+        json_object_set_new(rootJ, "destinations", destinationsJ);
+        // So that a duplicate of this module can tell it's a copy.
+        json_object_set_new(rootJ, "moduleId", json_integer(this->id));
+        return rootJ;
+    }
+
+    void dataFromJson(json_t *rootJ) override {
+        json_t *seedJ = json_object_get(rootJ, "seed");
+        json_t *positionJ = json_object_get(rootJ, "position");
+        if (!json_is_string(seedJ) || !json_is_string(positionJ)) {
+            return;
+        }
+        uint64_t seed = std::strtoull(json_string_value(seedJ), NULL, 10);
+        uint64_t position =
+            std::strtoull(json_string_value(positionJ), NULL, 10);
+
+        std::vector<size_t> destinations;
+        size_t i;
+        json_t *destinationJ;
+        json_array_foreach(json_object_get(rootJ, "destinations"), i,
+                           destinationJ) {
+            destinations.push_back(json_integer_value(destinationJ));
+        }
+        json_t *moduleIdJ = json_object_get(rootJ, "moduleId");
+        int64_t moduleId = moduleIdJ ? json_integer_value(moduleIdJ) : -1;
+        this->inner->restore_state(
+            seed, position,
+            rust::Slice<const size_t>(destinations.data(), destinations.size()),
+            moduleId);
+    }
+
+    // A duplicated module is restored from the original's data before it's
+    // added, so this is where it finds out it's a copy and needs a new seed.
+    void onAdd(const AddEvent &e) override {
+        this->inner->on_add(this->id, random::u64());
+    }
+};
+
+// A context menu text field for typing in a new seed, applied with Enter.
+struct SeedField : ui::TextField {
+    PolyShuffle *module;
+
+    SeedField(PolyShuffle *module) {
+        this->module = module;
+        box.size.x = 200.f;
+        placeholder = "Seed";
+        setText(std::to_string(module->snapshot->get_seed()));
+    }
+
+    void onSelectKey(const SelectKeyEvent &e) override {
+        if (e.action == GLFW_PRESS &&
+            (e.key == GLFW_KEY_ENTER || e.key == GLFW_KEY_KP_ENTER)) {
+            char *end = NULL;
+            uint64_t seed = std::strtoull(text.c_str(), &end, 10);
+            if (!text.empty() && *end == '\0') {
+                module->snapshot->request_seed(seed);
+            }
+            getAncestorOfType<ui::MenuOverlay>()->requestDelete();
+            e.consume(this);
+        }
+        if (!e.getTarget()) {
+            ui::TextField::onSelectKey(e);
+        }
     }
 };
 
@@ -48,9 +156,53 @@ struct PolyShuffleWidget : ModuleWidget {
         addInput(createInputCentered<PJ301MPort>(
             mm2px(Vec(7.62, 59.362)), module,
             PolyShuffle::SHUFFLE_TRIGGER_INPUT));
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 40.526)), module,
+            PolyShuffle::PERMUTATION_CV_INPUT));
+        addParam(createParamCentered<VCVButton>(
+            mm2px(Vec(22.86, 48.2)), module, PolyShuffle::RESET_BUTTON_PARAM));
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 59.362)), module,
+            PolyShuffle::RESET_TRIGGER_INPUT));
 
         addOutput(createOutputCentered<PJ301MPort>(
             mm2px(Vec(7.62, 78.198)), module, PolyShuffle::OUTPUT_OUTPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 78.198)), module,
+            PolyShuffle::PERMUTATION_CV_OUTPUT));
+
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(7.62, 100.0)), module, PolyShuffle::UNSHUFFLE_INPUT));
+        addOutput(createOutputCentered<PJ301MPort>(
+            mm2px(Vec(7.62, 114.5)), module, PolyShuffle::UNSHUFFLE_OUTPUT));
+
+        addInput(createInputCentered<PJ301MPort>(
+            mm2px(Vec(22.86, 100.0)), module, PolyShuffle::SORT_KEY_INPUT));
+    }
+
+    void appendContextMenu(Menu *menu) override {
+        PolyShuffle *module = getModule<PolyShuffle>();
+        menu->addChild(new MenuSeparator);
+        menu->addChild(createSwitchParamMenuItem(
+            "Order on trigger", module, PolyShuffle::ORDER_MODE_PARAM));
+        menu->addChild(createSwitchParamMenuItem(
+            "Sort", module, PolyShuffle::SORT_CONTINUOUSLY_PARAM));
+        menu->addChild(createSwitchParamMenuItem(
+            "Permutation CV", module, PolyShuffle::PERMUTATION_SOURCES_PARAM));
+        menu->addChild(createSwitchParamMenuItem(
+            "Permutation CV scaling", module,
+            PolyShuffle::PERMUTATION_SPREAD_PARAM));
+
+        menu->addChild(new MenuSeparator);
+        menu->addChild(createMenuLabel("Seed (press Enter to apply)"));
+        menu->addChild(new SeedField(module));
+        menu->addChild(createMenuItem("Randomize seed", "", [=]() {
+            module->snapshot->request_seed(random::u64());
+        }));
+        menu->addChild(createMenuItem("Use default seed", "", [=]() {
+            module->snapshot->request_seed(
+                rustlib::polyshuffle_default_seed());
+        }));
     }
 };
 